@@ -1,36 +1,166 @@
 use crate::notes::Note;
+use crate::patterns::PatternChannel;
+
+const SINE_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161,
+    180, 197, 212, 224, 235, 244, 250, 253,
+    255, 253, 250, 244, 235, 224, 212, 197,
+    180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+// Waveforms used by vibrato and tremolo, selected by E4x and E7x
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    RampDown,
+    Square,
+}
+
+impl Waveform {
+    pub fn from(value: u8) -> Waveform {
+        match value & 0x3 {
+            0 => Waveform::Sine,
+            1 => Waveform::RampDown,
+            _ => Waveform::Square,
+        }
+    }
+
+    /// The value of the waveform at a position from 0 to 63, ranging from -255 to 255
+    pub fn value(self, position: u8) -> i16 {
+        let position = position & 0x3f;
+        let value = match self {
+            Waveform::Sine => SINE_TABLE[(position & 0x1f) as usize] as i16,
+            Waveform::RampDown => {
+                let value = ((position & 0x1f) as i16) << 3;
+                if position >= 32 { 255 - value } else { value }
+            },
+            Waveform::Square => 255,
+        };
+
+        if position >= 32 { -value } else { value }
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct ChannelState {
+    pub sample: u8,
     pub volume: i8,
+    pub pan: u8,
     pub period: u16,
-    pub original_period: u16,
     pub finetune: i8,
     pub volume_slide: i8,
 
     pub arpeggio: (u8, u8),
-    pub portamento: i8,
-    pub slide_to_note: Option<(Note, u8)>,
+    pub portamento: i16,
+    pub glissando: bool,
+
+    pub tone_portamento: bool,
+    pub tone_portamento_target: u16,
+    pub tone_portamento_speed: u8,
+
+    pub vibrato: bool,
+    pub vibrato_speed: u8,
+    pub vibrato_depth: u8,
+    pub vibrato_position: u8,
+    pub vibrato_waveform: Waveform,
+    pub vibrato_retrigger: bool,
+
+    pub tremolo: bool,
+    pub tremolo_speed: u8,
+    pub tremolo_depth: u8,
+    pub tremolo_position: u8,
+    pub tremolo_waveform: Waveform,
+    pub tremolo_retrigger: bool,
+    pub tremolo_offset: i8,
+
+    pub sample_offset: u8,
+
+    pub loop_start: usize,
+    pub loop_count: u8,
 
     pub restart_sample_every: u8,
-    pub cut_sample_after: u8,
+    pub cut_sample_after: Option<u8>,
+    pub delayed_note: Option<(PatternChannel, u8)>,
 }
 
 impl ChannelState {
     pub fn new() -> Self {
         ChannelState {
+            sample: 0,
             volume: 64,
+            pan: 128,
             period: 0,
-            original_period: 0,
             finetune: 0,
             volume_slide: 0,
 
             arpeggio: (0, 0),
             portamento: 0,
-            slide_to_note: None,
+            glissando: false,
+
+            tone_portamento: false,
+            tone_portamento_target: 0,
+            tone_portamento_speed: 0,
+
+            vibrato: false,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+            vibrato_waveform: Waveform::Sine,
+            vibrato_retrigger: true,
+
+            tremolo: false,
+            tremolo_speed: 0,
+            tremolo_depth: 0,
+            tremolo_position: 0,
+            tremolo_waveform: Waveform::Sine,
+            tremolo_retrigger: true,
+            tremolo_offset: 0,
+
+            sample_offset: 0,
+
+            loop_start: 0,
+            loop_count: 0,
 
             restart_sample_every: 0,
-            cut_sample_after: 0,
+            cut_sample_after: None,
+            delayed_note: None,
+        }
+    }
+
+    /// Adjusts a period from the pattern data for the finetune of the channel
+    pub fn tune(&self, period: u16) -> u16 {
+        match Note::from(period, 0) {
+            Some(note) => note.get_period(self.finetune),
+            None => period
+        }
+    }
+
+    /// The current period, rounded to the nearest semitone while sliding with glissando on
+    pub fn slide_period(&self) -> u16 {
+        if self.glissando && (self.tone_portamento || self.portamento != 0) {
+            return Note::from_period_rounded(self.period, self.finetune).get_period(self.finetune);
         }
+        self.period
+    }
+
+    /// The period offset caused by vibrato on the current tick
+    pub fn vibrato_offset(&self) -> i16 {
+        if !self.vibrato { return 0; }
+        let value = self.vibrato_waveform.value(self.vibrato_position);
+        let offset = (value.abs() * self.vibrato_depth as i16) >> 7;
+        if value < 0 { -offset } else { offset }
+    }
+
+    /// Advances the tremolo by one tick and stores the resulting volume offset
+    pub fn update_tremolo(&mut self) {
+        let value = self.tremolo_waveform.value(self.tremolo_position);
+        let offset = ((value.abs() * self.tremolo_depth as i16) >> 6) as i8;
+        self.tremolo_offset = if value < 0 { -offset } else { offset };
+        self.tremolo_position = (self.tremolo_position + self.tremolo_speed) & 0x3f;
+    }
+
+    /// The volume the channel is mixed at on the current tick, including tremolo
+    pub fn output_volume(&self) -> i8 {
+        (self.volume as i16 + self.tremolo_offset as i16).clamp(0, 64) as i8
     }
 }
@@ -0,0 +1,30 @@
+use std::{error, fmt};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    /// The file ended before the song name, sample headers or pattern table
+    TruncatedHeader,
+    /// The file ended in the middle of the pattern data
+    TruncatedPatternData,
+    /// The file ended in the middle of the sample data of the given sample (1-based)
+    TruncatedSampleData(usize),
+    /// The file tag at offset 1080 is not one we know how to play
+    UnknownTag([u8; 4]),
+    /// The pattern table at the given position refers to an impossible pattern number
+    PatternIndexOutOfRange { position: usize, pattern: u8 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::TruncatedHeader => write!(f, "file is too short to contain a module header"),
+            LoadError::TruncatedPatternData => write!(f, "pattern data is truncated"),
+            LoadError::TruncatedSampleData(sample) => write!(f, "sample data of sample {} is truncated", sample),
+            LoadError::UnknownTag(tag) => write!(f, "unknown file tag {:?}", String::from_utf8_lossy(tag)),
+            LoadError::PatternIndexOutOfRange { position, pattern } =>
+                write!(f, "position {} refers to pattern {}, which is out of range", position, pattern),
+        }
+    }
+}
+
+impl error::Error for LoadError {}
@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+// Cutoff frequencies of the RC filters on the Amiga's audio output
+const A500_LOW_PASS: f64 = 4420.97;
+const A1200_LOW_PASS: f64 = 34419.32;
+const HIGH_PASS: f64 = 5.2;
+const LED_CUTOFF: f64 = 3090.53;
+const LED_Q: f64 = 0.660;
+
+/// Which Amiga's output filters are emulated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Off,
+    A500,
+    A1200,
+}
+
+/// A 6 dB per octave RC filter
+struct OnePole {
+    coefficient: f32,
+    state: [f32; 2],
+}
+
+impl OnePole {
+    fn new(cutoff: f64, sample_rate: f64) -> Self {
+        OnePole {
+            coefficient: (1.0 - (-2.0 * PI * cutoff / sample_rate).exp()) as f32,
+            state: [0.0; 2],
+        }
+    }
+
+    fn low_pass(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        for (state, sample) in self.state.iter_mut().zip(&frame) {
+            *state += self.coefficient * (sample - *state);
+        }
+        self.state
+    }
+
+    fn high_pass(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let low = self.low_pass(frame);
+        [frame[0] - low[0], frame[1] - low[1]]
+    }
+}
+
+/// The 12 dB per octave Butterworth filter behind the power LED
+struct LedFilter {
+    b: [f32; 3],
+    a: [f32; 2],
+    input: [[f32; 2]; 2],
+    output: [[f32; 2]; 2],
+}
+
+impl LedFilter {
+    fn new(sample_rate: f64) -> Self {
+        let w0 = 2.0 * PI * LED_CUTOFF / sample_rate;
+        let alpha = w0.sin() / (2.0 * LED_Q);
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - w0.cos()) / a0;
+        LedFilter {
+            b: [(b1 / 2.0) as f32, b1 as f32, (b1 / 2.0) as f32],
+            a: [(-2.0 * w0.cos() / a0) as f32, ((1.0 - alpha) / a0) as f32],
+            input: [[0.0; 2]; 2],
+            output: [[0.0; 2]; 2],
+        }
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let mut result = [0.0; 2];
+        for (c, sample) in frame.iter().enumerate() {
+            result[c] = self.b[0] * sample + self.b[1] * self.input[0][c] + self.b[2] * self.input[1][c]
+                - self.a[0] * self.output[0][c] - self.a[1] * self.output[1][c];
+        }
+        self.input = [frame, self.input[0]];
+        self.output = [result, self.output[0]];
+        result
+    }
+}
+
+pub struct AmigaFilter {
+    low_pass: Option<OnePole>,
+    led: LedFilter,
+    high_pass: OnePole,
+}
+
+impl AmigaFilter {
+    pub fn new(filter: Filter, sample_rate: f64) -> Option<Self> {
+        let cutoff = match filter {
+            Filter::Off => return None,
+            Filter::A500 => A500_LOW_PASS,
+            Filter::A1200 => A1200_LOW_PASS,
+        };
+
+        Some(AmigaFilter {
+            // The A1200's low-pass is above what most output rates can represent
+            low_pass: if cutoff < sample_rate / 2.0 { Some(OnePole::new(cutoff, sample_rate)) } else { None },
+            led: LedFilter::new(sample_rate),
+            high_pass: OnePole::new(HIGH_PASS, sample_rate),
+        })
+    }
+
+    /// Filters a frame, the LED filter always runs so switching it doesn't click
+    pub fn process(&mut self, frame: [f32; 2], led: bool) -> [f32; 2] {
+        let mut frame = match &mut self.low_pass {
+            Some(low_pass) => low_pass.low_pass(frame),
+            None => frame,
+        };
+
+        let filtered = self.led.process(frame);
+        if led { frame = filtered; }
+        self.high_pass.high_pass(frame)
+    }
+}
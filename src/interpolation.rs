@@ -0,0 +1,176 @@
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+use sample::Signal;
+
+use crate::samples::SampleCursor;
+
+/// Samples on either side of the playback position used by the sinc interpolation
+const SINC_TAPS: usize = 8;
+/// Samples that have been played and are kept for interpolation
+const HISTORY: usize = 32;
+
+/// How many output samples a band-limited step takes on either side of it
+const BLEP_WIDTH: usize = 8;
+const BLEP_RESOLUTION: usize = 32;
+/// Cutoff of the band-limited steps relative to the output rate, a bit below its nyquist frequency
+const BLEP_CUTOFF: f64 = 0.45;
+
+/// How samples are interpolated when they are resampled to the output rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// No interpolation, each sample is held until the next one like on the Amiga
+    Nearest,
+    Linear,
+    /// 4-point cubic Hermite
+    Cubic,
+    /// Windowed sinc, band-limited to the output rate when a sample plays faster than it
+    Sinc,
+    /// Paula's zero-order hold built from band-limited steps (BLEP), keeps the Amiga's
+    /// aliasing without the aliasing the output rate would add on top of it
+    Paula,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 }
+    else { (PI * x).sin() / (PI * x) }
+}
+
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+/// The integrated windowed sinc, rising from 0 to 1 over `BLEP_WIDTH` output samples on either side of a step
+fn blep_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let impulse: Vec<f64> = (0..=2 * BLEP_WIDTH * BLEP_RESOLUTION).map(|i| {
+            let x = i as f64 / BLEP_RESOLUTION as f64 - BLEP_WIDTH as f64;
+            sinc(2.0 * BLEP_CUTOFF * x) * blackman(x / BLEP_WIDTH as f64)
+        }).collect();
+
+        let mut sum = 0.0;
+        let mut table = vec![0.0];
+        for pair in impulse.windows(2) {
+            sum += (pair[0] + pair[1]) / 2.0;
+            table.push(sum);
+        }
+        table.iter().map(|value| (value / sum) as f32).collect()
+    })
+}
+
+/// How far a band-limited step has risen, `time` output samples after the step
+fn blep(time: f64) -> f32 {
+    let table = blep_table();
+    let position = (time + BLEP_WIDTH as f64) * BLEP_RESOLUTION as f64;
+    if position <= 0.0 { return 0.0; }
+    if position >= (table.len() - 1) as f64 { return 1.0; }
+
+    let i = position as usize;
+    table[i] + (table[i + 1] - table[i]) * (position - i as f64) as f32
+}
+
+/// Resamples a sample cursor to the output rate. The cursor already wraps around the sample loop,
+/// so the surrounding samples used by interpolation are the ones that are actually played
+#[derive(Clone)]
+pub struct Resampler<'a> {
+    cursor: SampleCursor<'a>,
+    interpolation: Interpolation,
+    // The last samples that were played, the current one is at the end
+    history: [f32; HISTORY],
+    position: f64,
+    step: f64,
+}
+
+impl<'a> Resampler<'a> {
+    pub fn new(mut cursor: SampleCursor<'a>, interpolation: Interpolation, source_hz: f64, target_hz: f64) -> Self {
+        let mut history = [0.0; HISTORY];
+        history[HISTORY - 1] = cursor.next()[0];
+        Resampler {
+            cursor, interpolation, history,
+            position: 0.0,
+            step: source_hz / target_hz,
+        }
+    }
+
+    pub fn source(&self) -> &SampleCursor<'a> { &self.cursor }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) { self.interpolation = interpolation; }
+
+    pub fn set_hz_to_hz(&mut self, source_hz: f64, target_hz: f64) {
+        self.step = source_hz / target_hz;
+    }
+
+    /// The sample `offset` places away from the current one, upcoming samples are read ahead without advancing
+    fn frame(&self, offset: isize) -> f32 {
+        if offset <= 0 {
+            return self.history[(HISTORY as isize - 1 + offset) as usize];
+        }
+
+        let mut cursor = self.cursor;
+        (1..offset).for_each(|_| { cursor.next(); });
+        cursor.next()[0]
+    }
+
+    fn interpolate(&self) -> f32 {
+        let x = self.position;
+        match self.interpolation {
+            Interpolation::Nearest => self.frame(0),
+            Interpolation::Linear => {
+                let (x0, x1) = (self.frame(0) as f64, self.frame(1) as f64);
+                (x0 + (x1 - x0) * x) as f32
+            },
+            Interpolation::Cubic => {
+                let (xm1, x0) = (self.frame(-1) as f64, self.frame(0) as f64);
+                let (x1, x2) = (self.frame(1) as f64, self.frame(2) as f64);
+                let c1 = 0.5 * (x1 - xm1);
+                let c2 = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+                let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+                (((c3 * x + c2) * x + c1) * x + x0) as f32
+            },
+            Interpolation::Sinc => {
+                // Lower the cutoff below the output's nyquist frequency when playing faster than the output rate
+                let cutoff = (1.0 / self.step).min(1.0);
+                let mut cursor = self.cursor;
+                let upcoming = (1..=SINC_TAPS).map(|_| cursor.next()[0]);
+                self.history[HISTORY - SINC_TAPS..].iter().copied().chain(upcoming).enumerate().map(|(n, frame)| {
+                    let distance = x - (n as f64 - (SINC_TAPS - 1) as f64);
+                    frame as f64 * cutoff * sinc(cutoff * distance) * blackman(distance / SINC_TAPS as f64)
+                }).sum::<f64>() as f32
+            },
+            Interpolation::Paula => {
+                // Every new sample is a step in Paula's output, the steps within reach of the
+                // playback position are added to the sample held before them
+                let reach = ((BLEP_WIDTH as f64 * self.step) as usize + 1).min(HISTORY - 1);
+                let mut cursor = self.cursor;
+                let mut upcoming = [0.0; HISTORY];
+                upcoming.iter_mut().take(reach).for_each(|frame| *frame = cursor.next()[0]);
+                let frame = |n: isize| {
+                    if n <= 0 { self.history[(HISTORY as isize - 1 + n) as usize] }
+                    else { upcoming[n as usize - 1] }
+                };
+
+                let reach = reach as isize;
+                ((1 - reach)..=reach).fold(frame(-reach), |output, n| {
+                    output + (frame(n) - frame(n - 1)) * blep((x - n as f64) / self.step)
+                })
+            },
+        }
+    }
+}
+
+impl Signal for Resampler<'_> {
+    type Frame = [f32; 1];
+
+    fn next(&mut self) -> Self::Frame {
+        while self.position >= 1.0 {
+            self.history.copy_within(1.., 0);
+            self.history[HISTORY - 1] = self.cursor.next()[0];
+            self.position -= 1.0;
+        }
+
+        let frame = self.interpolate();
+        self.position += self.step;
+        [frame]
+    }
+}
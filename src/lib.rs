@@ -0,0 +1,17 @@
+pub mod samples;
+pub mod patterns;
+pub mod notes;
+mod channel_state;
+mod error;
+mod filter;
+mod interpolation;
+mod module;
+mod player;
+mod wav;
+
+pub use crate::module::Module;
+pub use crate::error::LoadError;
+pub use crate::filter::Filter;
+pub use crate::interpolation::Interpolation;
+pub use crate::player::{Player, Timing, NTSC_CLOCK, PAL_CLOCK};
+pub use crate::wav::{WavFormat, WavWriter};
@@ -1,32 +1,31 @@
-use std::io::{Cursor, Read};
+mod ring_buffer;
+
 use std::thread;
-use std::sync::mpsc;
+use std::time::Duration;
+use std::collections::VecDeque;
+use std::io::{BufWriter, Write};
 use std::{env, fs, path::Path};
 
-use byteorder::{ReadBytesExt, WriteBytesExt, NativeEndian};
-use arr_macro::arr;
-
-use sample::{Frame, Signal};
-use sample::interpolate::{Converter, Floor};
+use byteorder::{WriteBytesExt, NativeEndian};
+use sample::Signal;
+use sample::interpolate::{Converter, Linear};
 
 use cpal::{StreamData, UnknownTypeOutputBuffer};
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
 
-use crate::samples::{Sample, SampleCursor};
-use crate::patterns::Pattern;
-use crate::notes::Note;
-use crate::channel_state::ChannelState;
+use rust_modplayer::{Filter, Interpolation, Module, Player, Timing, WavFormat, WavWriter, PAL_CLOCK};
 
-mod samples;
-mod patterns;
-mod notes;
-mod channel_state;
+use crate::ring_buffer::{ring_buffer, Consumer};
 
-static mut SAMPLE_RATE_CALC_VALUE: f64 = 7159090.5;
+// The ring buffer holds a fifth of a second, the audio output takes frames out of it in blocks
+const BUFFERS_PER_SECOND: usize = 5;
+const BLOCK_LENGTH: usize = 256;
+// Below this the song is barely recognisable, and the buffers would hold only a few frames
+const MIN_OUTPUT_RATE: u32 = 8000;
 
-fn sample_rate(period: u16) -> f64 {
-    let value = unsafe { SAMPLE_RATE_CALC_VALUE };
-    value / (period as f64 * 2.0)
+/// How many frames the ring buffer holds at a sample rate, at least one block
+fn ring_buffer_capacity(rate: u32) -> usize {
+    (rate as usize / BUFFERS_PER_SECOND).max(BLOCK_LENGTH)
 }
 
 fn main() {
@@ -34,96 +33,162 @@ fn main() {
     args.next();
     let file = args.next().expect("Provide a mod file as an argument");
     let file = Path::new(&file);
+    let render_file = match args.next().as_deref() {
+        Some("render") => Some(args.next().expect("Provide a file to render to")),
+        Some(mode) => panic!("Unknown mode {}, the only mode is render", mode),
+        None => None,
+    };
     let mod_data = fs::read(file).expect("Unable to read mod file");
 
-    match env::var("PAL_MODE") {
-        Ok(pal) => {
-            if pal != "0" && pal != "false" {
-                unsafe { SAMPLE_RATE_CALC_VALUE = 7093789.2; }
-            }
-        },
-        Err(_) => ()
+    let module = match Module::load(&mod_data) {
+        Ok(module) => module,
+        Err(err) => {
+            eprintln!("\nUnable to load {}: {}", file.display(), err);
+            return;
+        }
     };
 
-    let mut cursor = Cursor::new(mod_data.as_slice());
-    let song_name = {
-        let mut buf: [u8; 20] = [0; 20];
-        cursor.read_exact(&mut buf).unwrap();
-        
-        let mut len = 20;
-        for i in 0..len {
-            if buf[i] == 0 {
-                len = i;
-                break;
-            }
-        }
+    let nop_in_file = module.patterns().len();
+    eprintln!("Song name: {}", module.name());
+    eprintln!("Channels: {}", module.channels());
+    eprintln!("Samples: {:#?}", module.samples());
+    eprintln!("Pattern count: {}, Song End Jump Position: {}", module.song_length(), module.restart_position());
+    eprintln!("Patterns in file: {}", nop_in_file);
+    eprintln!();
 
-        String::from_utf8_lossy(&buf[0..len]).into_owned()
-    };
+    let mut player = Player::new(&module);
+    if let Ok(pal) = env::var("PAL_MODE") {
+        if pal != "0" && pal != "false" {
+            player.set_clock(PAL_CLOCK);
+        }
+    }
 
-    let mut samples: [Sample; 31] = arr![Sample::from(&mut cursor).unwrap(); 31];
-    let number_of_patterns = cursor.read_u8().unwrap();
-    let song_end_jump = cursor.read_u8().unwrap();
-    let mut pattern_table: [u8; 128] = [0; 128];
-    cursor.read_exact(&mut pattern_table).unwrap();
-    let file_tag = {
-        let mut buf: [u8; 4] = [0; 4];
-        cursor.read_exact(&mut buf).unwrap();
-        String::from_utf8_lossy(&buf).into_owned()
-    };
+    if let Ok(vblank) = env::var("VBLANK_TIMING") {
+        if vblank != "0" && vblank != "false" {
+            player.set_timing(Timing::VBlank);
+        }
+    }
 
-    let nop_in_file = pattern_table.iter().max().unwrap() + 1;
+    if let Ok(interpolation) = env::var("INTERPOLATION") {
+        player.set_interpolation(match interpolation.as_str() {
+            "nearest" => Interpolation::Nearest,
+            "linear" => Interpolation::Linear,
+            "cubic" => Interpolation::Cubic,
+            "sinc" => Interpolation::Sinc,
+            "paula" => Interpolation::Paula,
+            _ => panic!("INTERPOLATION has to be nearest, linear, cubic, sinc or paula"),
+        });
+    }
 
-    if file_tag != "M.K." && file_tag != "M!K!" && file_tag != "FLT4" && file_tag != "4CHN" {
-        eprintln!("\nFile has file tag {}, can only read M.K., M!K! or FLT4 files", file_tag);
-        return;
+    if let Ok(filter) = env::var("AMIGA_FILTER") {
+        player.set_filter(match filter.as_str() {
+            "a500" => Filter::A500,
+            "a1200" => Filter::A1200,
+            "off" => Filter::Off,
+            _ => panic!("AMIGA_FILTER has to be a500, a1200 or off"),
+        });
     }
 
-    eprintln!("Song name: {}", song_name);
-    eprintln!("Samples: {:#?}", samples);
-    eprintln!("Pattern count: {}, Song End Jump Position: {}", number_of_patterns, song_end_jump);
-    eprintln!("Patterns in file: {}", nop_in_file);
-    eprintln!();
-    
-    let mut patterns = Vec::new();
-    for _ in 0..nop_in_file {
-        let mut buf = [0 as u8; std::mem::size_of::<Pattern>()];
-        cursor.read_exact(&mut buf).unwrap();
-        patterns.push(Pattern::from(&buf[..]));
+    if let Ok(separation) = env::var("STEREO_SEPARATION") {
+        player.set_stereo_separation(separation.parse().expect("STEREO_SEPARATION has to be a percentage"));
     }
 
-    for sample in &mut samples {
-        if sample.length() > 0 {
-            let mut buf = vec![0; sample.length() as usize];
-            cursor.read_exact(&mut buf).unwrap();
-            sample.set_data(buf);
+    if let Ok(amiga) = env::var("AMIGA_PANNING") {
+        if amiga != "0" && amiga != "false" {
+            player.set_panning_effects(false);
         }
     }
 
+    // We don't want to infinitely pump to stdout or a file
+    player.set_song_loops(render_file.is_none() && atty::is(atty::Stream::Stdout));
+
+    let output_rate = env::var("OUTPUT_RATE").ok()
+        .map(|rate| match rate.parse::<u32>() {
+            Ok(rate) if rate >= MIN_OUTPUT_RATE => rate,
+            _ => panic!("OUTPUT_RATE has to be a sample rate of at least {} Hz", MIN_OUTPUT_RATE),
+        });
+
+    if let Some(render_file) = render_file {
+        let format = match env::var("WAV_FORMAT").as_deref() {
+            Ok("16") | Err(_) => WavFormat::Pcm16,
+            Ok("24") => WavFormat::Pcm24,
+            Ok("float") => WavFormat::Float32,
+            Ok(_) => panic!("WAV_FORMAT has to be 16, 24 or float"),
+        };
+        let channels = match env::var("WAV_CHANNELS").as_deref() {
+            Ok("2") | Err(_) => 2,
+            Ok("1") => 1,
+            Ok(_) => panic!("WAV_CHANNELS has to be 1 or 2"),
+        };
+        player.set_output_rate(output_rate.unwrap_or(44100));
+
+        let create_wav = |path: &Path| {
+            let writer = BufWriter::new(fs::File::create(path).expect("Unable to create the output file"));
+            WavWriter::new(writer, format, channels, player.output_rate()).expect("Unable to write the output file")
+        };
+        let render_file = Path::new(&render_file);
+        let mut wav = create_wav(render_file);
+
+        // Stems are written next to the mix as <name>-channel<n>.wav
+        let mut stems = Vec::new();
+        if let Ok(enabled) = env::var("STEMS") {
+            if enabled != "0" && enabled != "false" {
+                let name = render_file.file_stem().unwrap_or_default().to_string_lossy();
+                stems = (1..=module.channels())
+                    .map(|i| create_wav(&render_file.with_file_name(format!("{}-channel{}.wav", name, i))))
+                    .collect();
+            }
+        }
 
+        while let Some(frames) = player.next_tick() {
+            wav.write_frames(&frames).expect("Unable to write the output file");
+            for (stem, frames) in stems.iter_mut().zip(player.channel_frames()) {
+                stem.write_frames(frames).expect("Unable to write a stem file");
+            }
+        }
+        wav.finish().expect("Unable to write the output file");
+        for stem in stems {
+            stem.finish().expect("Unable to write a stem file");
+        }
+        eprintln!("Rendered to {}", render_file.display());
+        return;
+    }
 
-    // 10 seconds of buffer
-    let (tx, rx) = mpsc::sync_channel::<((u8, u8), <SampleCursor as Signal>::Frame)>(44100 / 50);
-
-    let audio_thread = {
+    let pattern_table = *module.pattern_table();
+    let (mut producer, audio_thread) = {
         if atty::is(atty::Stream::Stdout) {
+            let host = cpal::default_host();
+            let event_loop = host.event_loop();
+            let device = host.default_output_device().expect("no output device");
+            let formats: Vec<_> = device.supported_output_formats()
+                .expect("error while querying formats")
+                .filter(|format| format.data_type == cpal::SampleFormat::F32 && format.channels == 2)
+                .collect();
+            let supports = |rate: u32| formats.iter()
+                .find(|format| format.min_sample_rate.0 <= rate && format.max_sample_rate.0 >= rate);
+
+            // Render at the device's native rate unless another one was asked for,
+            // rates the device can't play are resampled to the native one
+            let native_rate = device.default_output_format().map(|format| format.sample_rate.0).unwrap_or(44100);
+            let render_rate = output_rate.unwrap_or(native_rate);
+            let device_rate = if supports(render_rate).is_some() { render_rate } else { native_rate };
+            let mut format = supports(device_rate).expect("No suitable format").clone().with_max_sample_rate();
+            format.sample_rate = cpal::SampleRate(device_rate);
+            let _stream_id = event_loop.build_output_stream(&device, &format).unwrap();
+
+            player.set_output_rate(render_rate);
+            if render_rate != device_rate {
+                eprintln!("Resampling from {} Hz to {} Hz", render_rate, device_rate);
+            }
+
+            let (producer, consumer) = ring_buffer::<[f32; 2]>(ring_buffer_capacity(render_rate));
+            let frames = BlockReader::new(consumer);
+            let mut frames = Converter::from_hz_to_hz(
+                frames, Linear::new([0.0; 2], [0.0; 2]),
+                render_rate as f64, device_rate as f64
+            );
+
             thread::spawn(move || {
-                let host = cpal::default_host();
-                let event_loop = host.event_loop();
-                let device = host.default_output_device().expect("no output device");
-                let mut supported_formats_range = device.supported_output_formats()
-                    .expect("error while querying formats");
-                let mut format = supported_formats_range.find(|format| {
-                    if format.data_type != cpal::SampleFormat::F32 { return false; }
-                    if format.channels != 1 { return false; }
-                    if format.min_sample_rate.0 > 44100 || format.max_sample_rate.0 < 44100 { return false; }
-                    true
-                }).expect("No suitable format").with_max_sample_rate();
-                format.sample_rate = cpal::SampleRate(44100);
-                let _stream_id = event_loop.build_output_stream(&device, &format).unwrap();
-
-                let mut current_pattern = 0;
-                let mut current_line = 0;
                 event_loop.run(move |stream_id, stream_result| {
                     let stream_data = match stream_result {
                         Ok(data) => data,
@@ -133,287 +198,110 @@ fn main() {
                         }
                     };
 
-                    match stream_data {
-                        StreamData::Output { buffer: UnknownTypeOutputBuffer::F32(mut buffer) } => {
-                            for elem in buffer.iter_mut() {
-                                match rx.try_recv() {
-                                    Ok(data) => {
-                                        if (data.0).0 != current_pattern || (data.0).1 != current_line {
-                                            current_pattern = (data.0).0;
-                                            current_line = (data.0).1;
-                                            eprintln!("Playing Pattern {:02X} (index {:02X}), Line {:02X}",
-                                                pattern_table[current_pattern as usize], current_pattern, current_line);
-                                        }
-
-                                        *elem = data.1[0];
-                                    },
-                                    Err(error) if error == mpsc::TryRecvError::Disconnected => {
-                                        panic!("MPSC channel disconnected");
-                                    },
-                                    Err(_) => *elem = 0.0
-                                }
-                            }
-                        },
-                        _ => (),
+                    if let StreamData::Output { buffer: UnknownTypeOutputBuffer::F32(mut buffer) } = stream_data {
+                        for elem in buffer.chunks_mut(2) {
+                            elem.copy_from_slice(&frames.next());
+                        }
                     }
                 });
-            })
+            });
+            (producer, None)
         } else {
-            thread::spawn(move || {
-                let mut current_pattern = 0;
-                let mut current_line = 0;
-                let mut stdout = std::io::stdout();
-                while let Ok(data) = rx.recv() {
-                    if (data.0).0 != current_pattern || (data.0).1 != current_line {
-                        current_pattern = (data.0).0;
-                        current_line = (data.0).1;
-                        eprintln!("Playing Pattern {:02X} (index {:02X}), Line {:02X}",
-                            pattern_table[current_pattern as usize], current_pattern, current_line);
+            player.set_output_rate(output_rate.unwrap_or(44100));
+            let (producer, mut consumer) = ring_buffer::<[f32; 2]>(ring_buffer_capacity(player.output_rate()));
+            let thread = thread::spawn(move || {
+                let mut stdout = BufWriter::new(std::io::stdout());
+                let mut block = [[0.0; 2]; BLOCK_LENGTH];
+                loop {
+                    let closed = consumer.is_closed();
+                    let count = consumer.pop(&mut block);
+                    if count == 0 {
+                        if closed { break; }
+                        thread::sleep(Duration::from_millis(1));
                     }
-                    stdout.write_f32::<NativeEndian>(data.1[0]).unwrap();
-                }
-            })
-        }
-    };
-
-    let mut current_speed = 6;
-    let mut current_tick = 0;
-    let mut current_line = 0;
-    let mut current_pattern = 0;
-    let mut processed_line = false;
-
-    let mut next_line = current_line;
-    let mut next_pattern = current_pattern;
-
-    let mut glissando = false;
-    let mut channel_state = [ChannelState::new(); 4];
-    let mut interpolators: [Option<Converter<SampleCursor, Floor<<SampleCursor as Signal>::Frame>>>; 4] = arr![None; 4];
-    //let mut interpolators: [Option<Converter<SampleCursor, Sinc<[<SampleCursor as Signal>::Frame; 8]>>>; 4] = arr![None; 4];
-    
-    'main: loop {
-        if next_pattern != current_pattern {
-            current_pattern = next_pattern;
-            processed_line = false;
-        }
-        if next_line != current_line {
-            current_line = next_line;
-            processed_line = false;
-        }
 
-        if !processed_line {
-            let line = &patterns[pattern_table[current_pattern] as usize][current_line];
-            for i in 0..4 {
-                let channel = line[i];
-                let effect = channel.effect();
-
-                channel_state[i].volume = channel_state[i].volume + channel_state[i].volume_slide;
-                if channel_state[i].volume > 64 { channel_state[i].volume = 64; channel_state[i].volume_slide = 0; }
-                if channel_state[i].volume < 0 { channel_state[i].volume = 0; channel_state[i].volume_slide = 0; }
-                
-                channel_state[i].arpeggio = (0, 0);
-                channel_state[i].portamento = 0;
-
-                channel_state[i].restart_sample_every = 0;
-                channel_state[i].cut_sample_after = 0;
-
-                match effect.number() {
-                    0x0 => { // Arpeggio
-                        if effect.arg_1() != 0 || effect.arg_1() != 0 {
-                            println!("Arpeggio on channel {}", i);
-                            channel_state[i].arpeggio = (effect.arg_1(), effect.arg_2());
-                        }
-                    }
-                    0x1 => channel_state[i].portamento = effect.arg_joined() as i8, // Portamento up,
-                    0x2 => channel_state[i].portamento = -(effect.arg_joined() as i8), // Portamento down
-                    0x3 => {
-                        match Note::from(channel.period()) {
-                            Some(note) => channel_state[i].slide_to_note = Some((note, effect.arg_joined())),
-                            None => ()
-                        }
-                    },
-                    0x5 => { // Continue Slide to Note, do Volume Slide
-                        if effect.arg_1() != 0 { channel_state[i].volume_slide = effect.arg_1() as i8; }
-                        else { channel_state[i].volume_slide = -(effect.arg_2() as i8); }
-                    },
-                    0x6 => { // Continue Vibrato, do Volume Slide
-                        if effect.arg_1() != 0 { channel_state[i].volume_slide = effect.arg_1() as i8; }
-                        else { channel_state[i].volume_slide = -(effect.arg_2() as i8); }
-                    },
-                    0xa => { // Volume Slide
-                        if effect.arg_1() != 0 { channel_state[i].volume_slide = effect.arg_1() as i8; }
-                        else { channel_state[i].volume_slide = -(effect.arg_2() as i8); }
-                    },
-                    0xb => { // Position Jump
-                        if atty::is(atty::Stream::Stdout) { // We don't want to infinitely pump to stdout
-                            next_pattern = effect.arg_joined() as usize;
-                            next_line = 0;
-                            current_tick = 0;
-                        }
-                    },
-                    0xc => { // Set Volume
-                        channel_state[i].volume_slide = 0;
-                        if effect.arg_joined() > 64 { channel_state[i].volume = 64; }
-                        else { channel_state[i].volume = effect.arg_joined() as i8; }
-                    },
-                    0xd => { // Pattern Break
-                        next_pattern += 1;
-                        next_line = ((effect.arg_1() * 10) + effect.arg_2()) as usize;
-                        current_tick = 0;
-                    },
-                    0xe => match effect.arg_1() { // Extended effects
-                        0x3 => { // Glissando (half a note slides)
-                            if effect.arg_2() != 0 { glissando = true; }
-                            else { glissando = false; }
-                        },
-                        0x5 => { // Set finetune
-                            match &interpolators[i] {
-                                Some(interpolator) => interpolator.source().sample().set_finetune(effect.arg_2() as i8), 
-                                None => ()
-                            }
-                        },
-                        0xc => {
-                            if effect.arg_2() == 0 { interpolators[i] = None }
-                            else { channel_state[i].cut_sample_after = effect.arg_2() }
-                        }
-                        _ => ()
-                    },
-                    0xf => { // Set Speed
-                        channel_state[i].volume_slide = 0;
-                        if effect.arg_joined() != 0 {
-                            current_speed = effect.arg_joined();
-                        }
+                    for frame in &block[..count] {
+                        stdout.write_f32::<NativeEndian>(frame[0]).unwrap();
+                        stdout.write_f32::<NativeEndian>(frame[1]).unwrap();
                     }
-                    _ => ()
                 }
+                stdout.flush().unwrap();
+            });
+            (producer, Some(thread))
+        }
+    };
 
-                if channel.number() != 0 && channel.period() != 0 {
-                    let sample = &samples[channel.number() as usize - 1];
-                    channel_state[i].period = channel.period();
-                    channel_state[i].original_period = channel.period();
-                    channel_state[i].finetune = sample.finetune();
-
-                    let mut cursor = SampleCursor::from(sample);
-                    //let interpolator = Linear::from_source(&mut cursor);
-                    let period = match Note::from(channel.period()) {
-                        Some(note) => note.get_period(sample.finetune()),
-                        None => channel.period()
-                    };
+    // The frame each rendered line starts at, printed once the audio output gets there
+    let mut positions = VecDeque::new();
+    let mut last_position = None;
+    let mut rendered = 0;
 
-                    //let buf = ring_buffer::Fixed::from(arr![cursor.next(); 8]);
-                    //let interpolator = Sinc::new(buf);
-                    let interpolator = Floor::from_source(&mut cursor);
-                    interpolators[i] = Some(Converter::from_hz_to_hz(
-                        cursor, interpolator,
-                        sample_rate(period), 44100.0
-                    ));
-                }
-            }
+    while let Some(frames) = player.next_tick() {
+        let position = player.position();
+        if last_position != Some(position) {
+            positions.push_back((rendered, position));
+            last_position = Some(position);
+        }
+        rendered += frames.len();
+
+        let mut written = 0;
+        loop {
+            written += producer.push(&frames[written..]);
+            print_positions(&pattern_table, &mut positions, producer.consumed());
+            if written == frames.len() { break; }
+            thread::sleep(Duration::from_millis(1));
         }
+    }
 
-        for i in 0..4 {
-            use std::io::{Seek, SeekFrom};
-            if channel_state[i].arpeggio != (0, 0) {
-                match Note::from(channel_state[i].original_period) {
-                    Some(note) => {
-                        let new_note = match current_tick % 3 {
-                            0 => note,
-                            1 => note.increment_half(channel_state[i].arpeggio.0),
-                            2 => note.increment_half(channel_state[i].arpeggio.1),
-                            _ => note,
-                        };
-
-                        let period = new_note.get_period(channel_state[i].finetune);
-                        channel_state[i].period = new_note.get_period(0);
-                        match &mut interpolators[i] {
-                            Some(interpolator) => interpolator.set_hz_to_hz(sample_rate(period), 44100.0),
-                            None => ()
-                        }
-                    },
-                    None => ()
-                }
-            }
+    eprintln!("\rDone converting                     \n");
+    while !producer.is_empty() {
+        print_positions(&pattern_table, &mut positions, producer.consumed());
+        thread::sleep(Duration::from_millis(1));
+    }
+    std::mem::drop(producer);
+    if let Some(audio_thread) = audio_thread { audio_thread.join().unwrap(); }
+}
 
-            if channel_state[i].portamento != 0 {
-                match Note::from(channel_state[i].period) {
-                    Some(note) => {
-                        let new_note = {
-                            if glissando == false {
-                                if channel_state[i].portamento > 0 { note.increment(channel_state[i].portamento as u8) }
-                                else { note.decrement((-channel_state[i].portamento) as u8) }
-                            } else {
-                                if channel_state[i].portamento > 0 { note.increment_half(channel_state[i].portamento as u8) }
-                                else { note.decrement_half((-channel_state[i].portamento) as u8) }
-                            }
-                        };
-
-                        let period = new_note.get_period(channel_state[i].finetune);
-                        channel_state[i].period = new_note.get_period(0);
-                        match &mut interpolators[i] {
-                            Some(interpolator) => interpolator.set_hz_to_hz(sample_rate(period), 44100.0),
-                            None => ()
-                        }
-                    },
-                    None => ()
-                };
-            }
+/// Prints the lines the audio output has reached
+fn print_positions(pattern_table: &[u8; 128], positions: &mut VecDeque<(usize, (usize, usize))>, consumed: usize) {
+    while let Some(&(frame, (pattern, line))) = positions.front() {
+        if frame > consumed { break; }
+        positions.pop_front();
+        eprintln!("Playing Pattern {:02X} (index {:02X}), Line {:02X}",
+            pattern_table[pattern], pattern, line);
+    }
+}
 
-            if channel_state[i].restart_sample_every != 0 && i % channel_state[i].restart_sample_every as usize == 0 {
-                match &mut interpolators[i] {
-                    Some(interpolator) => interpolator.source_mut().seek(SeekFrom::Start(0)).unwrap(),
-                    None => 0
-                };
-            }
+/// Reads rendered frames from the ring buffer a block at a time, playing silence when it runs dry
+struct BlockReader {
+    consumer: Consumer<[f32; 2]>,
+    block: [[f32; 2]; BLOCK_LENGTH],
+    position: usize,
+    length: usize,
+}
 
-            if channel_state[i].cut_sample_after != 0 && i == channel_state[i].cut_sample_after as usize {
-                interpolators[i] = None;
-            }
+impl BlockReader {
+    fn new(consumer: Consumer<[f32; 2]>) -> Self {
+        BlockReader {
+            consumer,
+            block: [[0.0; 2]; BLOCK_LENGTH],
+            position: 0,
+            length: 0,
         }
+    }
+}
 
-        current_tick += 1;
-
-        let mut frames: Vec<Vec<<SampleCursor as Signal>::Frame>> = Vec::new();
-        for (i, interpolator) in interpolators.iter_mut().enumerate() {
-            match interpolator {
-                Some(interpolator) => {
-                    let mut buf = Vec::new();
-                    for _ in 0..((44100.0 * 0.02) as usize) {
-                        let sample = interpolator.source().sample();
-                        let volume: [f32; 1] = [(channel_state[i].volume as f32) / 64.0];
-                        let volume: [f32; 1] = volume.mul_amp([(sample.volume() as f32) / 64.0]);
-                        buf.push(interpolator.next().mul_amp(volume));
-                        if interpolator.is_exhausted() { panic!("bruh"); }
-                    }
-                    frames.push(buf);
-                },
-                None => {
-                    let mut buf = Vec::new();
-                    for _ in 0..((44100.0 * 0.02) as usize) {
-                        buf.push([0.0]);
-                    }
-                    frames.push(buf);
-                }
-            }
-        }
+impl Signal for BlockReader {
+    type Frame = [f32; 2];
 
-        for j in 0..frames[0].len() {
-            let mut combined: [f32; 1] = frames[0][j].mul_amp([0.25]);
-            for i in 1..frames.len() {
-                combined = combined.add_amp(frames[i][j].mul_amp([0.25]));
-            }
-            tx.send(((current_pattern as u8, current_line as u8), combined)).unwrap();
+    fn next(&mut self) -> Self::Frame {
+        if self.position >= self.length {
+            self.length = self.consumer.pop(&mut self.block);
+            self.position = 0;
+            if self.length == 0 { return [0.0; 2]; }
         }
 
-        if current_tick >= current_speed {
-            current_tick = 0;
-            next_line += 1;
-        }
-        if next_line >= 64 {
-            next_line = 0;
-            next_pattern += 1;
-        }
-        if next_pattern >= number_of_patterns as usize { break 'main; }
+        self.position += 1;
+        self.block[self.position - 1]
     }
-    eprintln!("\rDone converting                     \n");
-    std::mem::drop(tx);
-    audio_thread.join().unwrap();
 }
@@ -0,0 +1,260 @@
+use std::io::{Cursor, Read};
+use std::convert::TryFrom;
+
+use byteorder::ReadBytesExt;
+use arr_macro::arr;
+
+use crate::samples::Sample;
+use crate::patterns::Pattern;
+use crate::error::LoadError;
+
+const MAX_PATTERNS: u8 = 128;
+const MAX_CHANNELS: usize = 32;
+const DEFAULT_TEMPO: u8 = 125;
+
+const TAG_OFFSET: usize = 1080;
+const SOUNDTRACKER_HEADER_LENGTH: usize = 20 + 15 * 30 + 2 + 128;
+
+/// Derives the channel count from the file tag at offset 1080
+fn channels_from_tag(tag: &[u8; 4]) -> Option<usize> {
+    let digit = |c: u8| if c.is_ascii_digit() { Some((c - b'0') as usize) } else { None };
+    let channels = match tag {
+        b"M.K." | b"M!K!" | b"FLT4" => 4,
+        b"CD81" | b"OCTA" | b"OKTA" => 8,
+        [c, b'C', b'H', b'N'] => digit(*c)?, // 6CHN, 8CHN, ...
+        [b'T', b'D', b'Z', c] => digit(*c)?,
+        [c1, c2, b'C', b'H'] | [c1, c2, b'C', b'N'] => digit(*c1)? * 10 + digit(*c2)?,
+        _ => return None,
+    };
+
+    if channels == 0 || channels > MAX_CHANNELS { None }
+    else { Some(channels) }
+}
+
+/// Checks whether the data looks like a 15-sample Soundtracker module, which has no tag
+fn is_soundtracker(data: &[u8]) -> bool {
+    if data.len() < SOUNDTRACKER_HEADER_LENGTH { return false; }
+
+    let samples_valid = data[20..470].chunks(30).all(|header| header[24] == 0 && header[25] <= 64);
+    let song_length = data[470];
+    let patterns_valid = data[472..600].iter().all(|&pattern| pattern < 64);
+
+    samples_valid && song_length > 0 && song_length <= 128 && patterns_valid
+}
+
+/// Ultimate Soundtracker stores the CIA timer speed where later trackers have the restart position
+fn soundtracker_tempo(value: u8) -> u8 {
+    if value == 0 || value == 0x78 || value >= 240 { DEFAULT_TEMPO }
+    else { ((709379 * 125 / 50) / ((240 - value as u32) * 122)).min(255) as u8 }
+}
+
+pub struct Module {
+    name: String,
+    channels: usize,
+    samples: [Sample; 31],
+    sample_count: usize,
+    song_length: u8,
+    restart_position: u8,
+    tempo: u8,
+    pattern_table: [u8; 128],
+    patterns: Vec<Pattern>,
+}
+
+impl Module {
+    pub fn name(&self) -> &str { &self.name }
+    pub fn channels(&self) -> usize { self.channels }
+    pub fn samples(&self) -> &[Sample; 31] { &self.samples }
+    /// How many samples the file has, 15 for Soundtracker modules and 31 otherwise
+    pub fn sample_count(&self) -> usize { self.sample_count }
+    pub fn song_length(&self) -> u8 { self.song_length }
+    pub fn restart_position(&self) -> u8 { self.restart_position }
+    pub fn tempo(&self) -> u8 { self.tempo }
+    pub fn pattern_table(&self) -> &[u8; 128] { &self.pattern_table }
+    pub fn patterns(&self) -> &[Pattern] { &self.patterns }
+
+    /// Parses a ProTracker module, or a 15-sample Soundtracker module if the tag is missing
+    pub fn load(data: &[u8]) -> Result<Self, LoadError> {
+        let file_tag = {
+            let mut buf: [u8; 4] = [0; 4];
+            if let Some(tag) = data.get(TAG_OFFSET..TAG_OFFSET + 4) { buf.copy_from_slice(tag); }
+            buf
+        };
+
+        let (sample_count, channels) = match channels_from_tag(&file_tag) {
+            Some(channels) => (31, channels),
+            None if is_soundtracker(data) => (15, 4),
+            None if data.len() < TAG_OFFSET + 4 => return Err(LoadError::TruncatedHeader),
+            None => return Err(LoadError::UnknownTag(file_tag)),
+        };
+
+        let mut cursor = Cursor::new(data);
+        let name = {
+            let mut buf: [u8; 20] = [0; 20];
+            cursor.read_exact(&mut buf).map_err(|_| LoadError::TruncatedHeader)?;
+
+            let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+            String::from_utf8_lossy(&buf[0..len]).into_owned()
+        };
+
+        let mut samples: [Sample; 31] = arr![Sample::default(); 31];
+        for sample in samples.iter_mut().take(sample_count) {
+            *sample = Sample::from(&mut cursor)?;
+        }
+
+        let song_length = cursor.read_u8().map_err(|_| LoadError::TruncatedHeader)?;
+        let mut restart_position = cursor.read_u8().map_err(|_| LoadError::TruncatedHeader)?;
+        let mut tempo = DEFAULT_TEMPO;
+        if sample_count == 15 {
+            tempo = soundtracker_tempo(restart_position);
+            restart_position = 0;
+        }
+
+        let mut pattern_table: [u8; 128] = [0; 128];
+        cursor.read_exact(&mut pattern_table).map_err(|_| LoadError::TruncatedHeader)?;
+        if sample_count == 31 {
+            cursor.set_position((TAG_OFFSET + 4) as u64);
+        }
+
+        if let Some(position) = pattern_table.iter().position(|&pattern| pattern >= MAX_PATTERNS) {
+            return Err(LoadError::PatternIndexOutOfRange { position, pattern: pattern_table[position] });
+        }
+
+        let nop_in_file = *pattern_table.iter().max().unwrap() as usize + 1;
+        let mut patterns = Vec::new();
+        for _ in 0..nop_in_file {
+            let mut buf = vec![0; 64 * channels * 4];
+            cursor.read_exact(&mut buf).map_err(|_| LoadError::TruncatedPatternData)?;
+            patterns.push(Pattern::try_from(&buf[..])?);
+        }
+
+        for (i, sample) in samples.iter_mut().enumerate() {
+            if sample.length() > 0 {
+                let mut buf = vec![0; sample.length() as usize];
+                cursor.read_exact(&mut buf).map_err(|_| LoadError::TruncatedSampleData(i + 1))?;
+                sample.set_data(buf);
+            }
+        }
+
+        Ok(Module {
+            name, channels, samples, sample_count,
+            song_length: song_length.min(128), restart_position, tempo,
+            pattern_table, patterns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 31-sample header with a song of one pattern and no pattern or sample data
+    fn header(tag: &[u8; 4]) -> Vec<u8> {
+        let mut data = vec![0; TAG_OFFSET + 4];
+        data[950] = 1;
+        data[TAG_OFFSET..].copy_from_slice(tag);
+        data
+    }
+
+    #[test]
+    fn channel_tags() {
+        for &(tag, channels) in &[
+            (b"M.K.", 4), (b"6CHN", 6), (b"8CHN", 8), (b"10CH", 10), (b"16CN", 16),
+            (b"TDZ4", 4), (b"CD81", 8), (b"OCTA", 8),
+        ] {
+            assert_eq!(channels_from_tag(tag), Some(channels), "{}", String::from_utf8_lossy(tag));
+        }
+        for tag in &[b"0CHN", b"99CH", b"ABCD", b"xCHN"] {
+            assert_eq!(channels_from_tag(tag), None, "{}", String::from_utf8_lossy(*tag));
+        }
+    }
+
+    #[test]
+    fn six_channels() {
+        let mut data = header(b"6CHN");
+        let mut pattern = vec![0; 64 * 6 * 4];
+        // Sample 1 at period 428 on the last channel of the second line
+        pattern[(6 + 5) * 4..(6 + 5) * 4 + 4].copy_from_slice(&[0x01, 0xac, 0x10, 0x00]);
+        data.extend(pattern);
+
+        let module = Module::load(&data).unwrap();
+        assert_eq!(module.channels(), 6);
+        assert_eq!(module.patterns().len(), 1);
+        let line = &module.patterns()[0][1];
+        assert_eq!(line.len(), 6);
+        assert_eq!(line[5].number(), 1);
+        assert_eq!(line[5].period(), 428);
+        assert_eq!(line[4].period(), 0);
+    }
+
+    /// A 15-sample module with one pattern, sample 1 is two words long
+    fn soundtracker(tempo: u8) -> Vec<u8> {
+        let mut data = vec![0; SOUNDTRACKER_HEADER_LENGTH];
+        data[20 + 22..20 + 30].copy_from_slice(&[0, 2, 0, 64, 0, 0, 0, 1]);
+        data[470] = 1;
+        data[471] = tempo;
+        data.extend_from_slice(&[0; 64 * 4 * 4]);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data
+    }
+
+    #[test]
+    fn soundtracker_detection() {
+        assert!(is_soundtracker(&soundtracker(0x78)));
+
+        let mut data = soundtracker(0x78);
+        data[20 + 25] = 65;
+        assert!(!is_soundtracker(&data));
+        assert!(!is_soundtracker(&header(b"M.K.")));
+    }
+
+    #[test]
+    fn soundtracker_tempos() {
+        assert_eq!(soundtracker_tempo(0x78), 125);
+        assert_eq!(soundtracker_tempo(0), DEFAULT_TEMPO);
+        assert_eq!(soundtracker_tempo(240), DEFAULT_TEMPO);
+        assert_eq!(soundtracker_tempo(255), DEFAULT_TEMPO);
+        assert_eq!(soundtracker_tempo(0x80), 129);
+    }
+
+    #[test]
+    fn fifteen_samples() {
+        let module = Module::load(&soundtracker(0x80)).unwrap();
+        assert_eq!(module.sample_count(), 15);
+        assert_eq!(module.channels(), 4);
+        assert_eq!(module.tempo(), 129);
+        assert_eq!(module.restart_position(), 0);
+        assert_eq!(module.samples()[0].data(), &[1, 2, 3, 4]);
+        assert_eq!(module.samples()[15].length(), 0);
+    }
+
+    #[test]
+    fn truncated_header() {
+        assert_eq!(Module::load(&[0; 100]).err(), Some(LoadError::TruncatedHeader));
+    }
+
+    #[test]
+    fn unknown_tag() {
+        assert_eq!(Module::load(&header(b"ABCD")).err(), Some(LoadError::UnknownTag(*b"ABCD")));
+    }
+
+    #[test]
+    fn pattern_index_out_of_range() {
+        let mut data = header(b"M.K.");
+        data[952 + 3] = 200;
+        assert_eq!(Module::load(&data).err(), Some(LoadError::PatternIndexOutOfRange { position: 3, pattern: 200 }));
+    }
+
+    #[test]
+    fn truncated_pattern_data() {
+        assert_eq!(Module::load(&header(b"M.K.")).err(), Some(LoadError::TruncatedPatternData));
+    }
+
+    #[test]
+    fn truncated_sample_data() {
+        let mut data = header(b"M.K.");
+        data[20 + 30 + 22..20 + 30 + 24].copy_from_slice(&[0, 10]);
+        data.extend_from_slice(&[0; 64 * 4 * 4]);
+        assert_eq!(Module::load(&data).err(), Some(LoadError::TruncatedSampleData(2)));
+    }
+
+}
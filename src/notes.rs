@@ -69,62 +69,23 @@ pub enum Note {
     B4,
 }
 
+const NOTES: [Note; 60] = [
+    Note::C0, Note::CSharp0, Note::D0, Note::DSharp0, Note::E0, Note::F0, Note::FSharp0, Note::G0, Note::GSharp0, Note::A0, Note::ASharp0, Note::B0,
+    Note::C1, Note::CSharp1, Note::D1, Note::DSharp1, Note::E1, Note::F1, Note::FSharp1, Note::G1, Note::GSharp1, Note::A1, Note::ASharp1, Note::B1,
+    Note::C2, Note::CSharp2, Note::D2, Note::DSharp2, Note::E2, Note::F2, Note::FSharp2, Note::G2, Note::GSharp2, Note::A2, Note::ASharp2, Note::B2,
+    Note::C3, Note::CSharp3, Note::D3, Note::DSharp3, Note::E3, Note::F3, Note::FSharp3, Note::G3, Note::GSharp3, Note::A3, Note::ASharp3, Note::B3,
+    Note::C4, Note::CSharp4, Note::D4, Note::DSharp4, Note::E4, Note::F4, Note::FSharp4, Note::G4, Note::GSharp4, Note::A4, Note::ASharp4, Note::B4,
+];
+
 impl Note {
-    pub fn from(period: u16) -> Option<Note> {
-        match period {
-            1712 => Some(Note::C0),
-            1616 => Some(Note::CSharp0),
-            1524 => Some(Note::D0),
-            1440 => Some(Note::DSharp0),
-            1356 => Some(Note::E0),
-            1280 => Some(Note::F0),
-            1208 => Some(Note::FSharp0),
-            1140 => Some(Note::G0),
-            1076 => Some(Note::GSharp0),
-            1016 => Some(Note::A0),
-            960 => Some(Note::ASharp0),
-            906 => Some(Note::B0),
-
-            856 => Some(Note::C1),
-            808 => Some(Note::CSharp1),
-            762 => Some(Note::D1),
-            720 => Some(Note::DSharp1),
-            678 => Some(Note::E1),
-            640 => Some(Note::F1),
-            604 => Some(Note::FSharp1),
-            570 => Some(Note::G1),
-            538 => Some(Note::GSharp1),
-            508 => Some(Note::A1),
-            480 => Some(Note::ASharp1),
-            453 => Some(Note::B1),
-
-            428 => Some(Note::C2),
-            404 => Some(Note::CSharp2),
-            381 => Some(Note::D2),
-            360 => Some(Note::DSharp2),
-            339 => Some(Note::E2),
-            320 => Some(Note::F2),
-            302 => Some(Note::FSharp2),
-            285 => Some(Note::G2),
-            269 => Some(Note::GSharp2),
-            254 => Some(Note::A2),
-            240 => Some(Note::ASharp2),
-            226 => Some(Note::B2),
-
-            214 => Some(Note::C3),
-            202 => Some(Note::CSharp3),
-            190 => Some(Note::D3),
-            180 => Some(Note::DSharp3),
-            170 => Some(Note::E3),
-            160 => Some(Note::F3),
-            151 => Some(Note::FSharp3),
-            143 => Some(Note::G2),
-            135 => Some(Note::GSharp2),
-            127 => Some(Note::A2),
-            120 => Some(Note::ASharp2),
-            113 => Some(Note::B2),
-            _ => None,
-        }
+    /// Finds the note that has the given period at the given finetune
+    pub fn from(period: u16, finetune: i8) -> Option<Note> {
+        NOTES.iter().copied().find(|note| note.get_period(finetune) == period)
+    }
+
+    /// Finds the highest note whose period is not longer than the given one
+    pub fn from_period_rounded(period: u16, finetune: i8) -> Note {
+        NOTES.iter().copied().find(|note| note.get_period(finetune) <= period).unwrap_or(Note::B4)
     }
 
     pub fn increment(self, by: u8) -> Note {
@@ -419,8 +380,10 @@ impl Note {
         note
     }
 
+    /// Looks up the period of the note in ProTracker's period table,
+    /// finetunes are -8 to 7, with 8 to 15 being treated as -8 to -1
     pub fn get_period(self, finetune: i8) -> u16 {
-        match finetune {
+        match (finetune << 4) >> 4 {
             0 => match self {
                 Note::C0 => 1712,
                 Note::CSharp0 => 1616,
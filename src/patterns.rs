@@ -1,11 +1,12 @@
 use std::ops::{Deref, DerefMut};
 use std::{fmt, io::Cursor};
-use std::io::Read;
-use std::mem::size_of;
+use std::convert::TryFrom;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use arr_macro::arr;
 
+use crate::error::LoadError;
+
 const LINES_PER_PATTERN: usize = 64;
 
 // Pattern
@@ -21,23 +22,26 @@ impl DerefMut for Pattern {
     fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
 }
 
-impl From<&[u8]> for Pattern {
-    fn from(buf: &[u8]) -> Self {
-        let mut cursor = Cursor::new(buf);
-        Pattern(
-            arr![{
-                let mut buf = [0; size_of::<PatternLine>()];
-                cursor.read_exact(&mut buf).unwrap();
-                PatternLine::from(&buf[..])
-            }; 64]
-        )
+impl TryFrom<&[u8]> for Pattern {
+    type Error = LoadError;
+
+    /// Reads a pattern of `buf.len() / 256` channels
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        if buf.is_empty() || !buf.len().is_multiple_of(LINES_PER_PATTERN * 4) {
+            return Err(LoadError::TruncatedPatternData);
+        }
+
+        let mut lines = buf.chunks(buf.len() / LINES_PER_PATTERN);
+        Ok(Pattern(
+            arr![PatternLine::try_from(lines.next().unwrap())?; 64]
+        ))
     }
 }
 
 // Pattern Line
 #[derive(Debug)]
 #[repr(transparent)]
-pub struct PatternLine([PatternChannel; 4]);
+pub struct PatternLine(Vec<PatternChannel>);
 
 impl Deref for PatternLine {
     type Target = [PatternChannel];
@@ -48,12 +52,20 @@ impl DerefMut for PatternLine {
     fn deref_mut(&mut self) -> &mut Self::Target { &mut self.0 }
 }
 
-impl From<&[u8]> for PatternLine {
-    fn from(buf: &[u8]) -> Self {
+impl TryFrom<&[u8]> for PatternLine {
+    type Error = LoadError;
+
+    fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+        if !buf.len().is_multiple_of(4) {
+            return Err(LoadError::TruncatedPatternData);
+        }
+
         let mut cursor = Cursor::new(buf);
-        PatternLine(
-            arr![PatternChannel(cursor.read_u32::<BigEndian>().unwrap()); 4]
-        )
+        let mut channels = Vec::with_capacity(buf.len() / 4);
+        for _ in 0..(buf.len() / 4) {
+            channels.push(PatternChannel(cursor.read_u32::<BigEndian>().map_err(|_| LoadError::TruncatedPatternData)?));
+        }
+        Ok(PatternLine(channels))
     }
 }
 
@@ -0,0 +1,820 @@
+use sample::{Frame, Signal};
+
+use crate::samples::{Sample, SampleCursor};
+use crate::interpolation::{Interpolation, Resampler};
+use crate::filter::{AmigaFilter, Filter};
+use crate::patterns::PatternChannel;
+use crate::notes::Note;
+use crate::channel_state::{ChannelState, Waveform};
+use crate::module::Module;
+
+pub const NTSC_CLOCK: f64 = 7159090.5;
+pub const PAL_CLOCK: f64 = 7093789.2;
+
+const MIN_PERIOD: u16 = 113;
+const MAX_PERIOD: u16 = 856;
+const MIN_EXTENDED_PERIOD: u16 = 56;
+const MAX_EXTENDED_PERIOD: u16 = 1814;
+
+/// How the replay speed is timed, old trackers only had the vertical blank interrupt
+/// and treat every Fxx as ticks per line, while newer ones use the CIA timer for BPM
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Cia,
+    VBlank,
+}
+
+/// The Amiga plays channels left, right, right, left
+fn amiga_pan(i: usize) -> u8 {
+    match i % 4 {
+        0 | 3 => 0,
+        _ => 255,
+    }
+}
+
+pub struct Player<'a> {
+    module: &'a Module,
+    clock: f64,
+    output_rate: f64,
+    timing: Timing,
+    extended_periods: bool,
+    interpolation: Interpolation,
+    filter: Filter,
+    // One filter per channel, so the channels still add up to the mix
+    filter_state: Vec<AmigaFilter>,
+    led_filter: bool,
+    stereo_separation: f32,
+    panning_effects: bool,
+    position_jumps: bool,
+    song_loops: bool,
+    played_positions: [bool; 128],
+
+    current_speed: u8,
+    current_tempo: u8,
+    tick_remainder: f64,
+    current_tick: u8,
+    current_line: usize,
+    current_pattern: usize,
+    rendered_position: (usize, usize),
+
+    position_jump: Option<usize>,
+    pattern_break: Option<usize>,
+    loop_jump: Option<usize>,
+    line_delay: u8,
+    repeating_line: bool,
+    finished: bool,
+
+    channel_state: Vec<ChannelState>,
+    interpolators: Vec<Option<Resampler<'a>>>,
+    channel_frames: Vec<Vec<[f32; 2]>>,
+
+    // The rest of a tick that didn't fit into the last buffer passed to `render`
+    pending: Vec<[f32; 2]>,
+    pending_position: usize,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Player {
+            module,
+            clock: NTSC_CLOCK,
+            output_rate: 44100.0,
+            timing: Timing::Cia,
+            extended_periods: false,
+            interpolation: Interpolation::Nearest,
+            filter: Filter::Off,
+            filter_state: Vec::new(),
+            led_filter: false,
+            stereo_separation: 1.0,
+            panning_effects: true,
+            position_jumps: true,
+            song_loops: true,
+            played_positions: {
+                let mut played = [false; 128];
+                played[0] = true;
+                played
+            },
+
+            current_speed: 6,
+            current_tempo: module.tempo(),
+            tick_remainder: 0.0,
+            current_tick: 0,
+            current_line: 0,
+            current_pattern: 0,
+            rendered_position: (0, 0),
+
+            position_jump: None,
+            pattern_break: None,
+            loop_jump: None,
+            line_delay: 0,
+            repeating_line: false,
+            finished: false,
+
+            channel_state: (0..module.channels()).map(|i| {
+                let mut state = ChannelState::new();
+                state.pan = amiga_pan(i);
+                state
+            }).collect(),
+            interpolators: (0..module.channels()).map(|_| None).collect(),
+            channel_frames: Vec::new(),
+
+            pending: Vec::new(),
+            pending_position: 0,
+        }
+    }
+
+    pub fn module(&self) -> &'a Module { self.module }
+
+    /// Sets the Paula clock used to turn periods into sample rates,
+    /// usually either `NTSC_CLOCK` or `PAL_CLOCK`
+    pub fn set_clock(&mut self, clock: f64) { self.clock = clock; }
+
+    /// Sets the sample rate the song is rendered at, 44100 Hz by default. Panics if the rate is 0
+    pub fn set_output_rate(&mut self, rate: u32) {
+        assert!(rate > 0, "the output rate can't be 0");
+        self.output_rate = rate as f64;
+        self.reset_filters();
+    }
+
+    pub fn output_rate(&self) -> u32 { self.output_rate as u32 }
+
+    pub fn set_timing(&mut self, timing: Timing) { self.timing = timing; }
+
+    /// Lets pitch slides go beyond ProTracker's three octaves, up to the periods of octaves 0 and 4
+    pub fn set_extended_periods(&mut self, enabled: bool) { self.extended_periods = enabled; }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+        for interpolator in self.interpolators.iter_mut().flatten() {
+            interpolator.set_interpolation(interpolation);
+        }
+    }
+
+    /// Emulates the output filters of an Amiga model, including the LED filter toggled by E0x
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.reset_filters();
+    }
+
+    fn reset_filters(&mut self) {
+        self.filter_state = (0..self.module.channels())
+            .filter_map(|_| AmigaFilter::new(self.filter, self.output_rate))
+            .collect();
+    }
+
+    /// Sets how far apart the left and right channels are, from 0% (mono) to 100% (hard panning like the Amiga)
+    pub fn set_stereo_separation(&mut self, percent: u8) { self.stereo_separation = percent.min(100) as f32 / 100.0; }
+
+    /// Whether 8xx and E8x set the panning of channels, disable them for strict Amiga playback
+    pub fn set_panning_effects(&mut self, enabled: bool) { self.panning_effects = enabled; }
+
+    /// Whether position jumps (Bxx) are followed, disabling them stops songs from looping forever
+    pub fn set_position_jumps(&mut self, enabled: bool) { self.position_jumps = enabled; }
+
+    /// Whether position jumps (Bxx) back to a position that has already been played are followed,
+    /// disabling this ends the song there instead of looping forever while jumps ahead still work
+    pub fn set_song_loops(&mut self, enabled: bool) { self.song_loops = enabled; }
+
+    /// The position table index and line of the last rendered tick
+    pub fn position(&self) -> (usize, usize) { self.rendered_position }
+
+    fn period_limits(&self) -> (u16, u16) {
+        if self.extended_periods { (MIN_EXTENDED_PERIOD, MAX_EXTENDED_PERIOD) }
+        else { (MIN_PERIOD, MAX_PERIOD) }
+    }
+
+    fn sample_rate(&self, period: u16) -> f64 {
+        self.clock / (period as f64 * 2.0)
+    }
+
+    /// Renders the next tick, returns `None` once the song has ended
+    pub fn next_tick(&mut self) -> Option<Vec<[f32; 2]>> {
+        if self.finished { return None; }
+
+        // The line is advanced after the tick, the position has to be kept from before that
+        self.rendered_position = (self.current_pattern, self.current_line);
+        if self.current_tick == 0 && !self.repeating_line {
+            self.process_line();
+        }
+
+        for i in 0..self.channel_state.len() {
+            self.process_tick(i);
+        }
+
+        // A tick lasts 2.5 / BPM seconds, the fractional part is carried over to the next tick
+        let tick_length = self.output_rate * 2.5 / self.current_tempo as f64 + self.tick_remainder;
+        self.tick_remainder = tick_length.fract();
+        let frames = self.mix(tick_length as usize);
+
+        self.current_tick += 1;
+        if self.current_tick >= self.current_speed {
+            self.current_tick = 0;
+            self.next_line();
+        }
+
+        Some(frames)
+    }
+
+    /// Fills `out` with interleaved stereo samples, continuing where the last call stopped.
+    /// Returns how many samples were written, which is less than `out.len()` once the song has ended
+    pub fn render(&mut self, out: &mut [f32]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            if self.pending_position >= self.pending.len() * 2 {
+                match self.next_tick() {
+                    Some(frames) => self.pending = frames,
+                    None => break,
+                }
+                self.pending_position = 0;
+            }
+
+            let pending = &self.pending.as_flattened()[self.pending_position..];
+            let count = pending.len().min(out.len() - written);
+            out[written..written + count].copy_from_slice(&pending[..count]);
+            self.pending_position += count;
+            written += count;
+        }
+        written
+    }
+
+    fn next_line(&mut self) {
+        // Pattern delay plays the line again without triggering its notes
+        if self.line_delay > 0 {
+            self.line_delay -= 1;
+            self.repeating_line = true;
+            return;
+        }
+        self.repeating_line = false;
+
+        let previous_pattern = self.current_pattern;
+        let jumped = self.position_jump.is_some();
+        let loop_jump = self.loop_jump.take();
+        if self.position_jump.is_some() || self.pattern_break.is_some() {
+            // A pattern break takes precedence over a pattern loop on the same line
+            self.current_pattern = self.position_jump.take().unwrap_or(self.current_pattern + 1);
+            self.current_line = self.pattern_break.take().unwrap_or(0);
+        } else if let Some(line) = loop_jump {
+            self.current_line = line;
+        } else {
+            self.current_line += 1;
+            if self.current_line >= 64 {
+                self.current_line = 0;
+                self.current_pattern += 1;
+            }
+        }
+
+        if self.current_pattern != previous_pattern {
+            for state in &mut self.channel_state {
+                state.loop_start = 0;
+                state.loop_count = 0;
+            }
+        }
+
+        if self.current_pattern != previous_pattern || jumped {
+            match self.played_positions.get_mut(self.current_pattern) {
+                Some(true) if !self.song_loops => self.finished = true,
+                Some(played) => *played = true,
+                None => (),
+            }
+        }
+
+        if self.current_pattern >= self.module.song_length() as usize { self.finished = true; }
+    }
+
+    fn set_period(&mut self, i: usize, period: u16) {
+        let sample_rate = self.sample_rate(period);
+        if let Some(interpolator) = &mut self.interpolators[i] {
+            interpolator.set_hz_to_hz(sample_rate, self.output_rate);
+        }
+    }
+
+    fn process_line(&mut self) {
+        let module = self.module;
+        let line = &module.patterns()[module.pattern_table()[self.current_pattern] as usize][self.current_line];
+        for (i, &channel) in line.iter().enumerate() {
+            let effect = channel.effect();
+            let state = &mut self.channel_state[i];
+
+            state.volume_slide = 0;
+            state.arpeggio = (0, 0);
+            state.portamento = 0;
+            state.vibrato = false;
+            state.tremolo = false;
+            state.tone_portamento = false;
+
+            state.restart_sample_every = 0;
+            state.cut_sample_after = None;
+            state.delayed_note = None;
+
+            // A sample number selects the sample of the next notes and resets the channel to its volume,
+            // numbers past the samples of the file are treated like no sample at all
+            let number = channel.number() as usize;
+            if number != 0 && number <= module.sample_count() {
+                state.sample = channel.number();
+                state.volume = module.samples()[number - 1].volume().min(64) as i8;
+            }
+
+            match effect.number() {
+                0x0 if effect.arg_1() != 0 || effect.arg_2() != 0 => { // Arpeggio
+                    state.arpeggio = (effect.arg_1(), effect.arg_2());
+                },
+                0x1 => state.portamento = effect.arg_joined() as i16, // Portamento up
+                0x2 => state.portamento = -(effect.arg_joined() as i16), // Portamento down
+                0x3 => { // Slide to Note
+                    state.tone_portamento = true;
+                    if effect.arg_joined() != 0 { state.tone_portamento_speed = effect.arg_joined(); }
+                },
+                0x4 => { // Vibrato
+                    state.vibrato = true;
+                    if effect.arg_1() != 0 { state.vibrato_speed = effect.arg_1(); }
+                    if effect.arg_2() != 0 { state.vibrato_depth = effect.arg_2(); }
+                },
+                0x5 => { // Continue Slide to Note, do Volume Slide
+                    state.tone_portamento = true;
+                    if effect.arg_1() != 0 { state.volume_slide = effect.arg_1() as i8; }
+                    else { state.volume_slide = -(effect.arg_2() as i8); }
+                },
+                0x6 => { // Continue Vibrato, do Volume Slide
+                    state.vibrato = true;
+                    if effect.arg_1() != 0 { state.volume_slide = effect.arg_1() as i8; }
+                    else { state.volume_slide = -(effect.arg_2() as i8); }
+                },
+                0x7 => { // Tremolo
+                    state.tremolo = true;
+                    if effect.arg_1() != 0 { state.tremolo_speed = effect.arg_1(); }
+                    if effect.arg_2() != 0 { state.tremolo_depth = effect.arg_2(); }
+                },
+                0x8 if self.panning_effects => state.pan = effect.arg_joined(), // Set Panning
+                0x9 if effect.arg_joined() != 0 => state.sample_offset = effect.arg_joined(), // Sample Offset
+                0xa => { // Volume Slide
+                    if effect.arg_1() != 0 { state.volume_slide = effect.arg_1() as i8; }
+                    else { state.volume_slide = -(effect.arg_2() as i8); }
+                },
+                0xb if self.position_jumps => { // Position Jump
+                    self.position_jump = Some(effect.arg_joined() as usize);
+                },
+                0xc => { // Set Volume
+                    if effect.arg_joined() > 64 { state.volume = 64; }
+                    else { state.volume = effect.arg_joined() as i8; }
+                },
+                0xd => { // Pattern Break
+                    let line = ((effect.arg_1() * 10) + effect.arg_2()) as usize;
+                    self.pattern_break = Some(if line >= 64 { 0 } else { line });
+                },
+                0xe => match effect.arg_1() { // Extended effects
+                    0x0 => self.led_filter = effect.arg_2() & 0x1 == 0, // Set Filter (0 turns the LED filter on)
+                    0x3 => state.glissando = effect.arg_2() != 0, // Glissando (half a note slides)
+                    0x4 => { // Set vibrato waveform
+                        state.vibrato_waveform = Waveform::from(effect.arg_2());
+                        state.vibrato_retrigger = effect.arg_2() & 0x4 == 0;
+                    },
+                    0x5 => state.finetune = ((effect.arg_2() << 4) as i8) >> 4, // Set finetune
+                    0x6 => { // Pattern Loop
+                        if effect.arg_2() == 0 {
+                            state.loop_start = self.current_line;
+                        } else {
+                            if state.loop_count == 0 {
+                                state.loop_count = effect.arg_2();
+                            } else {
+                                state.loop_count -= 1;
+                            }
+
+                            if state.loop_count != 0 {
+                                self.loop_jump = Some(state.loop_start);
+                            } else {
+                                // Like ProTracker, a finished loop doesn't jump back to its start again
+                                state.loop_start = self.current_line + 1;
+                            }
+                        }
+                    },
+                    0x7 => { // Set tremolo waveform
+                        state.tremolo_waveform = Waveform::from(effect.arg_2());
+                        state.tremolo_retrigger = effect.arg_2() & 0x4 == 0;
+                    },
+                    0x8 if self.panning_effects => state.pan = effect.arg_2() * 17, // Set Panning
+                    0x9 => state.restart_sample_every = effect.arg_2(), // Retrigger Note
+                    0xc => state.cut_sample_after = Some(effect.arg_2()), // Note Cut
+                    0xe => self.line_delay = effect.arg_2(), // Pattern Delay
+                    _ => ()
+                },
+                0xf if effect.arg_joined() != 0 => { // Set Speed
+                    if effect.arg_joined() < 0x20 || self.timing == Timing::VBlank {
+                        self.current_speed = effect.arg_joined();
+                    } else {
+                        self.current_tempo = effect.arg_joined();
+                    }
+                },
+                _ => ()
+            }
+
+            let state = &mut self.channel_state[i];
+            if state.tone_portamento && channel.period() != 0 {
+                // Slide to the new note instead of restarting the sample
+                state.tone_portamento_target = state.tune(channel.period());
+            } else if effect.number() == 0xe && effect.arg_1() == 0xd && effect.arg_2() != 0 {
+                // Note Delay, the note is triggered once the tick is reached
+                state.delayed_note = Some((channel, effect.arg_2()));
+            } else {
+                self.trigger_note(i, channel);
+            }
+
+            // Fine slides only happen once, after the note of the line has been set
+            let (min_period, max_period) = self.period_limits();
+            let state = &mut self.channel_state[i];
+            match (effect.number(), effect.arg_1()) {
+                (0xe, 0x1) if state.period != 0 => { // Fine Portamento up
+                    state.period = state.period.saturating_sub(effect.arg_2() as u16).max(min_period);
+                },
+                (0xe, 0x2) if state.period != 0 => { // Fine Portamento down
+                    state.period = (state.period + effect.arg_2() as u16).min(max_period);
+                },
+                (0xe, 0xa) => state.volume = (state.volume + effect.arg_2() as i8).min(64), // Fine Volume Slide up
+                (0xe, 0xb) => state.volume = (state.volume - effect.arg_2() as i8).max(0), // Fine Volume Slide down
+                _ => ()
+            }
+        }
+    }
+
+    fn trigger_note(&mut self, i: usize, channel: PatternChannel) {
+        // A note without a sample number plays the sample the channel had last
+        let number = self.channel_state[i].sample as usize;
+        if number == 0 || channel.period() == 0 { return; }
+
+        let sample = &self.module.samples()[number - 1];
+        if sample.length() == 0 {
+            // There's nothing to play in an empty sample slot
+            self.interpolators[i] = None;
+            return;
+        }
+
+        let state = &mut self.channel_state[i];
+        state.finetune = sample.finetune();
+        if channel.effect().number() == 0xe && channel.effect().arg_1() == 0x5 {
+            // A finetune on the same line already applies to the new note
+            state.finetune = ((channel.effect().arg_2() << 4) as i8) >> 4;
+        }
+        state.period = state.tune(channel.period());
+        if state.vibrato_retrigger { state.vibrato_position = 0; }
+        if state.tremolo_retrigger { state.tremolo_position = 0; }
+
+        let offset = match channel.effect().number() {
+            0x9 => state.sample_offset as usize * 256,
+            _ => 0
+        };
+        self.start_sample(i, sample, offset);
+    }
+
+    fn start_sample(&mut self, i: usize, sample: &'a Sample, offset: usize) {
+        let mut cursor = SampleCursor::from(sample);
+        cursor.set_offset(offset);
+
+        let period = self.channel_state[i].period;
+        self.interpolators[i] = Some(Resampler::new(
+            cursor, self.interpolation,
+            self.sample_rate(period), self.output_rate
+        ));
+    }
+
+    fn restart_sample(&mut self, i: usize) {
+        if let Some(interpolator) = &self.interpolators[i] {
+            let sample = interpolator.source().sample();
+            self.start_sample(i, sample, 0);
+        }
+    }
+
+    fn line_has_note(&self, i: usize) -> bool {
+        let module = self.module;
+        let line = &module.patterns()[module.pattern_table()[self.current_pattern] as usize][self.current_line];
+        line[i].period() != 0
+    }
+
+    fn process_tick(&mut self, i: usize) {
+        if let Some((channel, tick)) = self.channel_state[i].delayed_note {
+            if self.current_tick == tick {
+                self.channel_state[i].delayed_note = None;
+                self.trigger_note(i, channel);
+            }
+        }
+
+        // Retrigger Note, a note on the line has already restarted the sample on the first tick
+        let retrigger = self.channel_state[i].restart_sample_every;
+        if retrigger != 0 && self.current_tick.is_multiple_of(retrigger) && (self.current_tick != 0 || !self.line_has_note(i)) {
+            self.restart_sample(i);
+        }
+
+        // Slides also happen on the first tick of lines repeated by a pattern delay
+        let first_tick = self.current_tick == 0 && !self.repeating_line;
+        let (min_period, max_period) = self.period_limits();
+        let state = &mut self.channel_state[i];
+        // Like ProTracker, sliding up only stops at the minimum period and sliding down at the maximum
+        if !first_tick && state.portamento > 0 && state.period != 0 {
+            state.period = state.period.saturating_sub(state.portamento as u16).max(min_period);
+        } else if !first_tick && state.portamento < 0 && state.period != 0 {
+            state.period = (state.period + state.portamento.unsigned_abs()).min(max_period);
+        }
+
+        if !first_tick && state.tone_portamento && state.tone_portamento_target != 0 && state.period != 0 {
+            let target = state.tone_portamento_target;
+            let speed = state.tone_portamento_speed as u16;
+            if state.period < target { state.period = (state.period + speed).min(target); }
+            else { state.period = state.period.saturating_sub(speed).max(target); }
+        }
+
+        if !first_tick {
+            state.volume = (state.volume + state.volume_slide).clamp(0, 64);
+        }
+
+        state.tremolo_offset = 0;
+        if !first_tick && state.tremolo {
+            state.update_tremolo();
+        }
+
+        let mut period = state.slide_period() as i32;
+        if state.arpeggio != (0, 0) {
+            let note = Note::from_period_rounded(state.period, state.finetune);
+            period = match self.current_tick % 3 {
+                1 => note.increment_half(state.arpeggio.0).get_period(state.finetune) as i32,
+                2 => note.increment_half(state.arpeggio.1).get_period(state.finetune) as i32,
+                _ => period,
+            };
+        }
+
+        if !first_tick && state.vibrato {
+            period += state.vibrato_offset() as i32;
+            state.vibrato_position = (state.vibrato_position + state.vibrato_speed) & 0x3f;
+        }
+
+        if state.cut_sample_after == Some(self.current_tick) {
+            state.volume = 0;
+        }
+
+        if state.period != 0 {
+            self.set_period(i, period.max(1) as u16);
+        }
+    }
+
+    /// The left and right gain of a channel
+    fn channel_gain(&self, i: usize) -> [f32; 2] {
+        let pan = ((self.channel_state[i].pan as f32 - 127.5) / 127.5) * self.stereo_separation;
+        [(1.0 - pan) / 2.0, (1.0 + pan) / 2.0]
+    }
+
+    /// Every channel's share of the last rendered tick, these add up to the mix
+    pub fn channel_frames(&self) -> &[Vec<[f32; 2]>] { &self.channel_frames }
+
+    fn mix(&mut self, tick_length: usize) -> Vec<[f32; 2]> {
+        // Panned hard, one side gets the larger half of the channels when there's an odd number of them
+        let amp = 1.0 / self.interpolators.len().div_ceil(2) as f32;
+        let gains: Vec<[f32; 2]> = (0..self.interpolators.len()).map(|i| self.channel_gain(i).scale_amp(amp)).collect();
+
+        let mut frames = std::mem::take(&mut self.channel_frames);
+        frames.resize_with(self.interpolators.len(), Vec::new);
+        for (i, (interpolator, buf)) in self.interpolators.iter_mut().zip(&mut frames).enumerate() {
+            buf.clear();
+            match interpolator {
+                Some(interpolator) => {
+                    let volume: [f32; 1] = [(self.channel_state[i].output_volume() as f32) / 64.0];
+                    for _ in 0..tick_length {
+                        let frame = interpolator.next().mul_amp(volume);
+                        buf.push([frame[0], frame[0]].mul_amp(gains[i]));
+                    }
+                },
+                None => buf.resize(tick_length, [0.0; 2]),
+            }
+            // Silent channels still run through the filter, their tail has to decay
+            if let Some(filter) = self.filter_state.get_mut(i) {
+                for frame in buf.iter_mut() {
+                    *frame = filter.process(*frame, self.led_filter);
+                }
+            }
+        }
+
+        let mixed = (0..tick_length).map(|j| {
+            frames.iter().fold([0.0; 2], |combined: [f32; 2], channel| combined.add_amp(channel[j]))
+        }).collect();
+        self.channel_frames = frames;
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module with a single pattern and a looped sample, notes are `(line, channel, sample, period, effect)`
+    fn module_data(sample_volume: u8, notes: &[(usize, usize, u8, u16, u16)]) -> Vec<u8> {
+        let mut data = vec![0; 1084];
+        data[20 + 22..20 + 30].copy_from_slice(&[0, 32, 0, sample_volume, 0, 8, 0, 24]);
+        data[950] = 1;
+        data[1080..].copy_from_slice(b"M.K.");
+
+        let mut pattern = vec![0; 64 * 4 * 4];
+        for &(line, channel, sample, period, effect) in notes {
+            let offset = (line * 4 + channel) * 4;
+            pattern[offset..offset + 4].copy_from_slice(&[
+                (sample & 0xf0) | (period >> 8) as u8, period as u8, (sample << 4) | (effect >> 8) as u8, effect as u8,
+            ]);
+        }
+        data.extend(pattern);
+        data.extend((0..64).map(|i: u8| i.wrapping_mul(37)));
+        data
+    }
+
+    fn build_module(sample_volume: u8, notes: &[(usize, usize, u8, u16, u16)]) -> Module {
+        Module::load(&module_data(sample_volume, notes)).unwrap()
+    }
+
+    fn test_module() -> Module {
+        build_module(64, &[
+            (0, 0, 1, 428, 0x047),
+            (0, 1, 1, 214, 0x037),
+            (2, 2, 1, 320, 0xa02),
+            (4, 3, 1, 170, 0xf7d),
+            (8, 0, 1, 254, 0xd00),
+        ])
+    }
+
+    fn play_ticks(player: &mut Player, ticks: usize) {
+        for _ in 0..ticks { player.next_tick(); }
+    }
+
+    fn render_in_chunks(module: &Module, interpolation: Interpolation, chunk: usize) -> Vec<f32> {
+        let mut player = Player::new(module);
+        player.set_interpolation(interpolation);
+        let mut output = Vec::new();
+        let mut buffer = vec![0.0; chunk];
+        loop {
+            let length = player.render(&mut buffer);
+            output.extend_from_slice(&buffer[..length]);
+            if length < buffer.len() { return output; }
+        }
+    }
+
+    #[test]
+    fn render_is_independent_of_buffer_size() {
+        let module = test_module();
+        for &interpolation in &[Interpolation::Nearest, Interpolation::Cubic, Interpolation::Sinc, Interpolation::Paula] {
+            let whole = render_in_chunks(&module, interpolation, 1 << 20);
+            assert!(!whole.is_empty());
+            assert!(whole.iter().any(|&sample| sample != 0.0));
+            for &chunk in &[1, 37, 4096] {
+                assert!(whole == render_in_chunks(&module, interpolation, chunk), "{:?} in chunks of {}", interpolation, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn render_matches_ticks() {
+        let module = test_module();
+        let mut player = Player::new(&module);
+        let mut ticks: Vec<f32> = Vec::new();
+        while let Some(frames) = player.next_tick() {
+            ticks.extend(frames.iter().flatten());
+        }
+        assert!(ticks == render_in_chunks(&module, Interpolation::Nearest, 37));
+    }
+
+    #[test]
+    fn channels_add_up_to_the_filtered_mix() {
+        let module = test_module();
+        let mut player = Player::new(&module);
+        player.set_filter(Filter::A500);
+        for _ in 0..20 {
+            let frames = player.next_tick().unwrap();
+            for (j, frame) in frames.iter().enumerate() {
+                let sum = player.channel_frames().iter().fold([0.0; 2], |sum, channel| [sum[0] + channel[j][0], sum[1] + channel[j][1]]);
+                assert!((sum[0] - frame[0]).abs() < 1e-5 && (sum[1] - frame[1]).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn position_is_the_rendered_line() {
+        let module = test_module();
+        let mut player = Player::new(&module);
+        for _ in 0..6 {
+            player.next_tick();
+            assert_eq!(player.position(), (0, 0));
+        }
+        player.next_tick();
+        assert_eq!(player.position(), (0, 1));
+    }
+
+    #[test]
+    fn volume_is_absolute() {
+        let module = build_module(32, &[
+            (0, 0, 1, 428, 0xc40),
+            (1, 0, 1, 0, 0x000),
+            (2, 0, 0, 0, 0xa40),
+        ]);
+        let mut player = Player::new(&module);
+        play_ticks(&mut player, 1);
+        assert_eq!(player.channel_state[0].volume, 64);
+        play_ticks(&mut player, 6);
+        assert_eq!(player.channel_state[0].volume, 32);
+        play_ticks(&mut player, 11);
+        assert_eq!(player.channel_state[0].volume, 52);
+    }
+
+    #[test]
+    fn notes_without_sample_number_retrigger() {
+        let module = build_module(64, &[
+            (0, 0, 1, 428, 0x000),
+            (1, 0, 0, 214, 0x000),
+            (2, 0, 0, 320, 0xed2),
+        ]);
+        let mut player = Player::new(&module);
+        play_ticks(&mut player, 7);
+        assert_eq!(player.channel_state[0].period, 214);
+        play_ticks(&mut player, 7);
+        assert_eq!(player.channel_state[0].period, 214);
+        play_ticks(&mut player, 1);
+        assert_eq!(player.channel_state[0].period, 320);
+    }
+
+    #[test]
+    fn vibrato_starts_on_the_second_tick() {
+        let module = build_module(64, &[(0, 0, 1, 428, 0x48f)]);
+        let mut player = Player::new(&module);
+        play_ticks(&mut player, 1);
+        assert_eq!(player.channel_state[0].vibrato_position, 0);
+        assert_eq!(player.channel_state[0].vibrato_offset(), 0);
+
+        // Tick 1 plays the sine at position 0 and moves on by the speed, the base period is left alone
+        play_ticks(&mut player, 1);
+        assert_eq!(player.channel_state[0].vibrato_position, 8);
+        assert_eq!(player.channel_state[0].vibrato_offset(), (180 * 15) >> 7);
+        assert_eq!(player.channel_state[0].period, 428);
+    }
+
+    #[test]
+    fn tone_portamento_stops_at_the_target() {
+        let module = build_module(64, &[
+            (0, 0, 1, 428, 0x000),
+            (1, 0, 0, 400, 0x310),
+        ]);
+        let mut player = Player::new(&module);
+        play_ticks(&mut player, 7);
+        assert_eq!(player.channel_state[0].period, 428);
+        assert_eq!(player.channel_state[0].tone_portamento_target, 400);
+        play_ticks(&mut player, 1);
+        assert_eq!(player.channel_state[0].period, 412);
+        play_ticks(&mut player, 4);
+        assert_eq!(player.channel_state[0].period, 400);
+    }
+
+    fn played_lines(player: &mut Player, count: usize) -> Vec<usize> {
+        let mut lines = Vec::new();
+        while lines.len() < count && player.next_tick().is_some() {
+            if player.current_tick == 1 { lines.push(player.position().1); }
+        }
+        lines
+    }
+
+    #[test]
+    fn pattern_loop_repeats_count_times() {
+        let module = build_module(64, &[(0, 0, 1, 428, 0xe60), (1, 0, 0, 0, 0xe62)]);
+        let mut player = Player::new(&module);
+        assert_eq!(played_lines(&mut player, 7), vec![0, 1, 0, 1, 0, 1, 2]);
+        assert_eq!(player.channel_state[0].loop_count, 0);
+    }
+
+    #[test]
+    fn pattern_delay_repeats_the_line() {
+        let module = build_module(64, &[(0, 0, 1, 428, 0xee2)]);
+        let mut player = Player::new(&module);
+        assert_eq!(played_lines(&mut player, 4), vec![0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn high_speed_values_set_the_tempo() {
+        let module = build_module(64, &[(1, 0, 0, 0, 0xf80), (2, 0, 0, 0, 0xf03)]);
+        let mut player = Player::new(&module);
+        // 2.5 / 125 seconds per tick at the default tempo, then 2.5 / 128
+        assert_eq!(player.next_tick().unwrap().len(), 882);
+        play_ticks(&mut player, 5);
+        assert_eq!(player.next_tick().unwrap().len(), 861);
+        assert_eq!(player.current_speed, 6);
+
+        // Values below 0x20 still set the speed
+        play_ticks(&mut player, 6);
+        assert_eq!(player.current_speed, 3);
+        assert_eq!(player.current_tempo, 128);
+    }
+
+    #[test]
+    fn song_ends_on_jumps_back() {
+        // Every position plays the same pattern, which jumps ahead to position 2 on its first line
+        let mut data = module_data(64, &[(0, 0, 1, 428, 0xb02)]);
+        data[950] = 3;
+        let module = Module::load(&data).unwrap();
+
+        let mut player = Player::new(&module);
+        player.set_song_loops(false);
+        let mut positions = Vec::new();
+        while player.next_tick().is_some() {
+            if positions.last() != Some(&player.position()) { positions.push(player.position()); }
+        }
+        assert_eq!(positions, vec![(0, 0), (2, 0)]);
+    }
+}
@@ -0,0 +1,78 @@
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A lock-free ring buffer with a single producer and a single consumer,
+/// both ends copy whole blocks and only synchronise once per block
+struct Shared<T> {
+    buffer: Box<[UnsafeCell<T>]>,
+    // Total number of elements written and read, the buffer index is taken modulo its length
+    written: AtomicUsize,
+    read: AtomicUsize,
+    closed: AtomicBool,
+}
+
+// The producer only writes to free slots and the consumer only reads filled ones
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub fn ring_buffer<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity).map(|_| UnsafeCell::new(T::default())).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+impl<T: Copy> Producer<T> {
+    /// Writes as much of `data` as fits, returns how many elements were written
+    pub fn push(&mut self, data: &[T]) -> usize {
+        let shared = &*self.shared;
+        let written = shared.written.load(Ordering::Relaxed);
+        let free = shared.buffer.len() - written.wrapping_sub(shared.read.load(Ordering::Acquire));
+        let count = free.min(data.len());
+        for (i, &element) in data[..count].iter().enumerate() {
+            unsafe { *shared.buffer[written.wrapping_add(i) % shared.buffer.len()].get() = element; }
+        }
+        shared.written.store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// The total number of elements the consumer has read so far
+    pub fn consumed(&self) -> usize { self.shared.read.load(Ordering::Acquire) }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.written.load(Ordering::Relaxed) == self.consumed()
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) { self.shared.closed.store(true, Ordering::Release); }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Fills the start of `out` with the oldest elements, returns how many elements were read
+    pub fn pop(&mut self, out: &mut [T]) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let available = shared.written.load(Ordering::Acquire).wrapping_sub(read);
+        let count = available.min(out.len());
+        for (i, element) in out[..count].iter_mut().enumerate() {
+            *element = unsafe { *shared.buffer[read.wrapping_add(i) % shared.buffer.len()].get() };
+        }
+        shared.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Whether the producer is gone, there can still be elements left to read
+    pub fn is_closed(&self) -> bool { self.shared.closed.load(Ordering::Acquire) }
+}
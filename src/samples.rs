@@ -1,15 +1,16 @@
 #![allow(dead_code)]
 use std::io::{self, Cursor, Read, Seek, SeekFrom};
-use std::cell::RefCell;
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder};
 
 use sample::Signal;
 
-#[derive(Debug)]
+use crate::error::LoadError;
+
+#[derive(Debug, Default)]
 pub struct Sample {
     name: String,
     length: u32,
-    finetune: RefCell<i8>,
+    finetune: i8,
     volume: u8,
     repeat_offset: u32,
     repeat_length: u32,
@@ -18,8 +19,7 @@ pub struct Sample {
 
 impl Sample {
     pub fn name(&self) -> &str { &self.name }
-    pub fn finetune(&self) -> i8 { *self.finetune.borrow() }
-    pub fn set_finetune(&self, finetune: i8) { *self.finetune.borrow_mut() = finetune; }
+    pub fn finetune(&self) -> i8 { self.finetune }
     pub fn volume(&self) -> u8 { self.volume }
 
     pub fn length(&self) -> u32 { self.length }
@@ -31,38 +31,36 @@ impl Sample {
         self.data = buf;
     }
 
-    pub fn from(cursor: &mut Cursor<&[u8]>) -> std::io::Result<Self> {
+    pub fn from(cursor: &mut Cursor<&[u8]>) -> Result<Self, LoadError> {
         let mut buf: [u8; 30] = [0; 30];
-        cursor.read_exact(&mut buf)?;
+        cursor.read_exact(&mut buf).map_err(|_| LoadError::TruncatedHeader)?;
         Ok((&buf).into())
     }
 }
 
 impl From<&[u8; 30]> for Sample {
     fn from(other: &[u8; 30]) -> Self {
-        let mut len = 22;
-        for i in 0..len {
-            if other[i] == 0 {
-                len = i;
-                break;
-            }
-        }
+        let len = other[0..22].iter().position(|&c| c == 0).unwrap_or(22);
 
         let name = String::from_utf8_lossy(&other[0..len]).into_owned();
-        let mut cursor = Cursor::new(&other[22..30]);
 
-        let length = cursor.read_u16::<BigEndian>().unwrap();
-        let finetune = cursor.read_i8().unwrap();
-        let volume = cursor.read_u8().unwrap();
-        let repeat_offset = cursor.read_u16::<BigEndian>().unwrap();
-        let repeat_length = cursor.read_u16::<BigEndian>().unwrap();
+        let length = BigEndian::read_u16(&other[22..24]);
+        let finetune = other[24] as i8;
+        let volume = other[25];
+        let repeat_offset = BigEndian::read_u16(&other[26..28]);
+        let repeat_length = BigEndian::read_u16(&other[28..30]);
+
+        // Some modules have loops reaching past the end of the sample, those are cut off at the end
+        let length = length as u32 * 2;
+        let (repeat_offset, repeat_length) = match repeat_offset as u32 * 2 {
+            offset if offset >= length => (0, 0),
+            offset => (offset, (repeat_length as u32 * 2).min(length - offset)),
+        };
 
         Sample {
-            name, volume,
-            length: length as u32 * 2,
-            repeat_offset: repeat_offset as u32 * 2,
-            repeat_length: repeat_length as u32 * 2,
-            finetune: RefCell::new((finetune & 0x07) - (finetune & 0x08)),
+            name, volume, length,
+            repeat_offset, repeat_length,
+            finetune: (finetune & 0x07) - (finetune & 0x08),
             data: Vec::new()
         }
     }
@@ -86,6 +84,12 @@ impl<'a> SampleCursor<'a> {
 
     pub fn sample(&self) -> &'a Sample { self.sample }
 
+    /// Starts playback at the given byte offset, offsets past the end of the
+    /// sample continue with its loop like ProTracker does
+    pub fn set_offset(&mut self, offset: usize) {
+        self.offset = offset.min(self.sample.length as usize);
+    }
+
     fn read_byte(&mut self) -> f32 {
         use sample::Sample;
         if  self.offset >= self.sample.length as usize
@@ -94,7 +98,7 @@ impl<'a> SampleCursor<'a> {
             self.repeating = true;
         }
         self.offset += 1;
-        self.sample.data[self.offset - 1].to_sample::<f32>()
+        (self.sample.data[self.offset - 1] as i8).to_sample::<f32>()
     }
 }
 
@@ -134,3 +138,33 @@ impl Signal for SampleCursor<'_> {
         [self.read_byte()]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(length: u16, repeat_offset: u16, repeat_length: u16) -> [u8; 30] {
+        let mut buf = [0; 30];
+        BigEndian::write_u16(&mut buf[22..24], length);
+        BigEndian::write_u16(&mut buf[26..28], repeat_offset);
+        BigEndian::write_u16(&mut buf[28..30], repeat_length);
+        buf
+    }
+
+    #[test]
+    fn loop_is_cut_off_at_the_end() {
+        let sample: Sample = (&header(100, 80, 40)).into();
+        assert_eq!((sample.repeat_offset(), sample.repeat_length()), (160, 40));
+    }
+
+    #[test]
+    fn loop_past_the_end_is_dropped() {
+        let sample: Sample = (&header(100, 500, 10)).into();
+        assert_eq!((sample.repeat_offset(), sample.repeat_length()), (0, 0));
+
+        let mut sample = sample;
+        sample.set_data(vec![1; 200]);
+        let mut cursor = SampleCursor::from(&sample);
+        for _ in 0..1000 { cursor.next(); }
+    }
+}
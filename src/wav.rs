@@ -0,0 +1,216 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+// Offsets of the length fields that are filled in once all samples have been written
+const RIFF_LENGTH_OFFSET: u64 = 4;
+const FACT_LENGTH_OFFSET: u64 = 46;
+
+/// Sample formats of a WAVE file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavFormat {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Pcm24 => 3,
+            WavFormat::Float32 => 4,
+        }
+    }
+}
+
+/// Writes stereo frames to a RIFF WAVE file, mixing them down if the file is mono
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: WavFormat,
+    channels: u16,
+    frames: u32,
+    data_length_offset: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, format: WavFormat, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        if channels != 1 && channels != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "WAVE files can only be written in mono or stereo"));
+        }
+        let block_align = channels * format.bytes_per_sample();
+
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        // Formats other than PCM have an extension size and a fact chunk with the length in frames
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(if format == WavFormat::Float32 { 18 } else { 16 })?;
+        writer.write_u16::<LittleEndian>(if format == WavFormat::Float32 { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM })?;
+        writer.write_u16::<LittleEndian>(channels)?;
+        writer.write_u32::<LittleEndian>(sample_rate)?;
+        writer.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(format.bytes_per_sample() * 8)?;
+        if format == WavFormat::Float32 {
+            writer.write_u16::<LittleEndian>(0)?;
+            writer.write_all(b"fact")?;
+            writer.write_u32::<LittleEndian>(4)?;
+            writer.write_u32::<LittleEndian>(0)?;
+        }
+
+        writer.write_all(b"data")?;
+        let data_length_offset = writer.stream_position()?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter { writer, format, channels, frames: 0, data_length_offset })
+    }
+
+    fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        let sample = sample.clamp(-1.0, 1.0);
+        match self.format {
+            WavFormat::Pcm16 => self.writer.write_i16::<LittleEndian>((sample * i16::MAX as f32).round() as i16),
+            WavFormat::Pcm24 => self.writer.write_i24::<LittleEndian>((sample * 0x7fffff as f32).round() as i32),
+            WavFormat::Float32 => self.writer.write_f32::<LittleEndian>(sample),
+        }
+    }
+
+    /// The length of the data chunk holding `frames` frames, if the file stays within the 4 GiB RIFF limit
+    fn data_length(&self, frames: u64) -> Option<u32> {
+        let data_length = frames * (self.channels * self.format.bytes_per_sample()) as u64;
+        let riff_length = self.data_length_offset + 4 + data_length + data_length % 2 - 8;
+        if riff_length > u32::MAX as u64 { None } else { Some(data_length as u32) }
+    }
+
+    pub fn write_frames(&mut self, frames: &[[f32; 2]]) -> io::Result<()> {
+        let total_frames = self.frames as u64 + frames.len() as u64;
+        if self.data_length(total_frames).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "WAVE files can't be larger than 4 GiB"));
+        }
+
+        for frame in frames {
+            if self.channels == 1 {
+                self.write_sample((frame[0] + frame[1]) / 2.0)?;
+            } else {
+                self.write_sample(frame[0])?;
+                self.write_sample(frame[1])?;
+            }
+        }
+        self.frames = total_frames as u32;
+        Ok(())
+    }
+
+    /// Fills in the length fields, returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        // Writing frames already made sure the file stays within the limit
+        let data_length = self.data_length(self.frames as u64).unwrap();
+        // Chunks have to be of even length
+        if data_length % 2 == 1 { self.writer.write_u8(0)?; }
+        let riff_length = self.data_length_offset as u32 + 4 + data_length + data_length % 2 - 8;
+
+        self.writer.seek(SeekFrom::Start(RIFF_LENGTH_OFFSET))?;
+        self.writer.write_u32::<LittleEndian>(riff_length)?;
+        if self.format == WavFormat::Float32 {
+            self.writer.seek(SeekFrom::Start(FACT_LENGTH_OFFSET))?;
+            self.writer.write_u32::<LittleEndian>(self.frames)?;
+        }
+        self.writer.seek(SeekFrom::Start(self.data_length_offset))?;
+        self.writer.write_u32::<LittleEndian>(data_length)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 { u16::from_le_bytes([data[offset], data[offset + 1]]) }
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+    }
+
+    fn write(format: WavFormat, channels: u16, frames: usize) -> Vec<u8> {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), format, channels, 48000).unwrap();
+        wav.write_frames(&vec![[0.5, -0.5]; frames]).unwrap();
+        wav.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn pcm_headers() {
+        for &(format, bits) in &[(WavFormat::Pcm16, 16), (WavFormat::Pcm24, 24)] {
+            let data = write(format, 2, 10);
+            let data_length = 10 * 2 * bits as usize / 8;
+            assert_eq!(&data[0..4], b"RIFF");
+            assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+            assert_eq!(&data[8..16], b"WAVEfmt ");
+            assert_eq!(u32_at(&data, 16), 16);
+            assert_eq!(u16_at(&data, 20), WAVE_FORMAT_PCM);
+            assert_eq!(u16_at(&data, 22), 2);
+            assert_eq!(u32_at(&data, 24), 48000);
+            assert_eq!(u32_at(&data, 28), 48000 * 2 * bits / 8);
+            assert_eq!(u16_at(&data, 32), 2 * bits as u16 / 8);
+            assert_eq!(u16_at(&data, 34), bits as u16);
+            assert_eq!(&data[36..40], b"data");
+            assert_eq!(u32_at(&data, 40) as usize, data_length);
+            assert_eq!(data.len(), 44 + data_length);
+        }
+    }
+
+    #[test]
+    fn float_header() {
+        let data = write(WavFormat::Float32, 2, 10);
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(u32_at(&data, 16), 18);
+        assert_eq!(u16_at(&data, 20), WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!(u16_at(&data, 34), 32);
+        assert_eq!(u16_at(&data, 36), 0);
+        assert_eq!(&data[38..42], b"fact");
+        assert_eq!(u32_at(&data, FACT_LENGTH_OFFSET as usize), 10);
+        assert_eq!(&data[50..54], b"data");
+        assert_eq!(u32_at(&data, 54), 10 * 2 * 4);
+        assert_eq!(&data[58..62], &0.5f32.to_le_bytes());
+        assert_eq!(data.len(), 58 + 10 * 2 * 4);
+    }
+
+    #[test]
+    fn odd_data_length_is_padded() {
+        let data = write(WavFormat::Pcm24, 1, 3);
+        assert_eq!(u32_at(&data, 40), 9);
+        assert_eq!(data.len(), 44 + 9 + 1);
+        assert_eq!(data[data.len() - 1], 0);
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+
+        let data = write(WavFormat::Pcm16, 1, 3);
+        assert_eq!(u32_at(&data, 40), 6);
+        assert_eq!(data.len(), 44 + 6);
+    }
+
+    #[test]
+    fn mono_is_mixed_down() {
+        let data = write(WavFormat::Pcm16, 1, 1);
+        assert_eq!(u16_at(&data, 44), 0);
+    }
+
+    #[test]
+    fn unsupported_channel_counts() {
+        let error = WavWriter::new(Cursor::new(Vec::new()), WavFormat::Pcm16, 3, 44100).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn files_stay_below_4_gib() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), WavFormat::Float32, 2, 48000).unwrap();
+        wav.frames = (u32::MAX / 8) - 10;
+        let error = wav.write_frames(&[[0.0; 2]; 10]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(wav.frames, (u32::MAX / 8) - 10);
+        wav.write_frames(&[[0.0; 2]; 2]).unwrap();
+    }
+}